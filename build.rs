@@ -1,20 +1,59 @@
 use std::{
-    env,
+    env, fs,
     path::{Path, PathBuf},
     process::Command,
 };
 
+use header::{Architecture, FormatError, NetHeader};
+
+#[allow(dead_code)]
+#[path = "src/eval/header.rs"]
+mod header;
+
 const REPO_URL: &str = "https://raw.githubusercontent.com/jeff-pow/FileHydrant/main/";
 const NET_NAME: &str = "net02.bin";
 
+/// Architecture the default net was trained for. It predates headers, so the build script gives it
+/// one the same way `convertnet` would, which lets the engine check it against its own
+/// architecture.
+#[rustfmt::skip]
+const DEFAULT_NET_ARCH: Architecture = Architecture {
+    input_size: 768,
+    hidden_size: 1536,
+    num_buckets: 9,
+    qa: 255,
+    qb: 64,
+    buckets: [
+        0, 1, 2, 3, 12, 11, 10, 9,
+        4, 4, 5, 5, 14, 14, 13, 13,
+        6, 6, 6, 6, 15, 15, 15, 15,
+        7, 7, 7, 7, 16, 16, 16, 16,
+        8, 8, 8, 8, 17, 17, 17, 17,
+        8, 8, 8, 8, 17, 17, 17, 17,
+        8, 8, 8, 8, 17, 17, 17, 17,
+        8, 8, 8, 8, 17, 17, 17, 17,
+    ],
+    output_buckets: 1,
+};
+
 fn main() {
-    let path = match env::var("EVALFILE") {
+    println!("cargo::rustc-check-cfg=cfg(raw_net)");
+    // Offline builds embed a hand-crafted net rather than a trained one
+    if env::var_os("CARGO_FEATURE_OFFLINE").is_some() {
+        return;
+    }
+
+    let evalfile = env::var("EVALFILE");
+    let path = match &evalfile {
         Ok(evalfile) => {
-            if PathBuf::from(&evalfile).is_relative() {
+            let path = if Path::new(evalfile).is_relative() {
                 Path::new(env!("CARGO_MANIFEST_DIR")).join(evalfile)
             } else {
-                PathBuf::from(&evalfile)
-            }
+                PathBuf::from(evalfile)
+            };
+            check_evalfile(&path);
+            println!("cargo:rerun-if-changed={}", path.display());
+            path
         }
         Err(_) => {
             if !Path::new(NET_NAME).exists() {
@@ -25,11 +64,71 @@ fn main() {
                 }
             }
 
-            Path::new(env!("CARGO_MANIFEST_DIR")).join(NET_NAME)
+            let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(NET_NAME);
+            println!("cargo:rerun-if-changed={}", path.display());
+            headered_default_net(&path)
         }
     };
 
     println!("cargo:rerun-if-env-changed=EVALFILE");
-    println!("cargo:rerun-if-changed={NET_NAME}");
+    println!("cargo:rerun-if-env-changed=EVALFILE_RAW");
+    println!("cargo:rerun-if-changed=src/eval/header.rs");
     println!("cargo:rustc-env=NETWORK={}", path.display());
 }
+
+/// Returns the path of a copy of the default net with a header in front of it, written to
+/// `OUT_DIR` unless the net already has one
+fn headered_default_net(path: &Path) -> PathBuf {
+    let raw = read(path);
+    match NetHeader::parse(&raw) {
+        Ok((header, weights)) => {
+            check_hash(path, &header, weights);
+            return path.to_path_buf();
+        }
+        Err(FormatError::MissingHeader) => (),
+        Err(e) => panic!("{}: {e}", path.display()),
+    }
+
+    let mut bytes = NetHeader::new(DEFAULT_NET_ARCH, &raw).to_bytes().to_vec();
+    bytes.extend_from_slice(&raw);
+    let copy = Path::new(&env::var("OUT_DIR").unwrap()).join(NET_NAME);
+    fs::write(&copy, bytes).unwrap_or_else(|e| panic!("Failed to write {}: {e}", copy.display()));
+
+    let bytes = read(&copy);
+    let (header, weights) = NetHeader::parse(&bytes).unwrap_or_else(|e| panic!("{}: {e}", copy.display()));
+    assert_eq!(header.arch, DEFAULT_NET_ARCH, "{} has the wrong architecture", copy.display());
+    check_hash(&copy, &header, weights);
+    copy
+}
+
+/// Checks the hash of a net given through `EVALFILE`, leaving its architecture to be checked by the
+/// engine itself. Nets without a header can't be checked at all, so they are only embedded when
+/// `EVALFILE_RAW=1` says they were trained for this build.
+fn check_evalfile(path: &Path) {
+    let bytes = read(path);
+    match NetHeader::parse(&bytes) {
+        Ok((header, weights)) => check_hash(path, &header, weights),
+        Err(FormatError::MissingHeader) if env::var("EVALFILE_RAW").is_ok_and(|raw| raw == "1") => {
+            println!(
+                "cargo:warning={} has no header, so nothing checks it matches this build's architecture",
+                path.display()
+            );
+            println!("cargo:rustc-cfg=raw_net");
+        }
+        Err(FormatError::MissingHeader) => panic!(
+            "{} has no header. Add one with `titan convertnet`, or set EVALFILE_RAW=1 to embed it unchecked",
+            path.display()
+        ),
+        Err(e) => panic!("{}: {e}", path.display()),
+    }
+}
+
+fn check_hash(path: &Path, header: &NetHeader, weights: &[u8]) {
+    if let Err(e) = header.check_hash(weights) {
+        panic!("{}: {e}", path.display());
+    }
+}
+
+fn read(path: &Path) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|e| panic!("Failed to read {}: {e}", path.display()))
+}
@@ -0,0 +1,222 @@
+# Reference perft for the Chess960 start positions in src/fischer_perft.epd. It shares no code with
+# Titan's move generator, so the counts it prints can be used to check it.
+#
+#   python3 chess960_perft.py                   prints every start position with its counts
+#   python3 chess960_perft.py <epd> <max depth>  checks the counts of an existing suite
+import sys
+
+KNIGHT = [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)]
+KING = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)]
+DIAGONALS = [(1, 1), (1, -1), (-1, 1), (-1, -1)]
+ORTHOGONALS = [(1, 0), (-1, 0), (0, 1), (0, -1)]
+
+
+def on_board(file, rank):
+    return 0 <= file < 8 and 0 <= rank < 8
+
+
+def is_white(piece):
+    return piece.isupper()
+
+
+def own(piece, white):
+    return piece.upper() if white else piece.lower()
+
+
+class Position:
+    def __init__(self, board, white, castling, ep):
+        # Squares are indexed rank * 8 + file, starting from a1
+        self.board = board
+        self.white = white
+        # Castling rights as (white, rook square) pairs
+        self.castling = castling
+        self.ep = ep
+
+
+def parse(fen):
+    fields = fen.split()
+    board = [None] * 64
+    for idx, row in enumerate(fields[0].split("/")):
+        rank, file = 7 - idx, 0
+        for c in row:
+            if c.isdigit():
+                file += int(c)
+            else:
+                board[rank * 8 + file] = c
+                file += 1
+
+    castling = set()
+    for c in fields[2].replace("-", ""):
+        white = c.isupper()
+        rank = 0 if white else 7
+        king = next(f for f in range(8) if board[rank * 8 + f] == own("K", white))
+        rooks = [f for f in range(8) if board[rank * 8 + f] == own("R", white)]
+        if c.lower() == "k":
+            rook = max(f for f in rooks if f > king)
+        elif c.lower() == "q":
+            rook = min(f for f in rooks if f < king)
+        else:
+            rook = ord(c.lower()) - ord("a")
+        castling.add((white, rank * 8 + rook))
+
+    ep = None if fields[3] == "-" else (int(fields[3][1]) - 1) * 8 + ord(fields[3][0]) - ord("a")
+    return Position(board, fields[1] == "w", castling, ep)
+
+
+def attacked(board, sq, by_white):
+    file, rank = sq % 8, sq // 8
+    for piece, deltas in (("N", KNIGHT), ("K", KING)):
+        for df, dr in deltas:
+            if on_board(file + df, rank + dr) and board[(rank + dr) * 8 + file + df] == own(piece, by_white):
+                return True
+    pawn_rank = rank - 1 if by_white else rank + 1
+    for df in (-1, 1):
+        if on_board(file + df, pawn_rank) and board[pawn_rank * 8 + file + df] == own("P", by_white):
+            return True
+    for deltas, sliders in ((DIAGONALS, "BQ"), (ORTHOGONALS, "RQ")):
+        for df, dr in deltas:
+            f, r = file + df, rank + dr
+            while on_board(f, r):
+                piece = board[r * 8 + f]
+                if piece is not None:
+                    if is_white(piece) == by_white and piece.upper() in sliders:
+                        return True
+                    break
+                f, r = f + df, r + dr
+    return False
+
+
+def pseudolegal(pos):
+    board, white, moves = pos.board, pos.white, []
+    for sq, piece in enumerate(board):
+        if piece is None or is_white(piece) != white:
+            continue
+        file, rank = sq % 8, sq // 8
+        kind = piece.upper()
+        if kind == "P":
+            forward, start, last = (1, 1, 7) if white else (-1, 6, 0)
+            promotions = "QRBN" if rank + forward == last else [None]
+            to = sq + 8 * forward
+            if board[to] is None:
+                moves += [(sq, to, promo) for promo in promotions]
+                if rank == start and board[to + 8 * forward] is None:
+                    moves.append((sq, to + 8 * forward, None))
+            for df in (-1, 1):
+                if not on_board(file + df, rank + forward):
+                    continue
+                to = (rank + forward) * 8 + file + df
+                if board[to] is not None and is_white(board[to]) != white:
+                    moves += [(sq, to, promo) for promo in promotions]
+                elif to == pos.ep:
+                    moves.append((sq, to, "ep"))
+        elif kind in "NK":
+            for df, dr in KNIGHT if kind == "N" else KING:
+                to = (rank + dr) * 8 + file + df
+                if on_board(file + df, rank + dr) and (board[to] is None or is_white(board[to]) != white):
+                    moves.append((sq, to, None))
+        else:
+            deltas = {"B": DIAGONALS, "R": ORTHOGONALS, "Q": DIAGONALS + ORTHOGONALS}[kind]
+            for df, dr in deltas:
+                f, r = file + df, rank + dr
+                while on_board(f, r):
+                    to = r * 8 + f
+                    if board[to] is None or is_white(board[to]) != white:
+                        moves.append((sq, to, None))
+                    if board[to] is not None:
+                        break
+                    f, r = f + df, r + dr
+
+    # Castling is encoded as the king capturing its own rook
+    back_rank = 0 if white else 7
+    king = board.index(own("K", white))
+    for side, rook in pos.castling:
+        if side != white:
+            continue
+        kingside = rook > king
+        king_to = back_rank * 8 + (6 if kingside else 2)
+        rook_to = back_rank * 8 + (5 if kingside else 3)
+        squares = range(min(king, king_to, rook, rook_to), max(king, king_to, rook, rook_to) + 1)
+        if any(board[s] is not None for s in squares if s not in (king, rook)):
+            continue
+        if any(attacked(board, s, not white) for s in range(min(king, king_to), max(king, king_to) + 1)):
+            continue
+        moves.append((king, rook, "castle"))
+    return moves
+
+
+def make_move(pos, move):
+    start, to, flag = move
+    board, white = pos.board[:], pos.white
+    castling, ep = set(pos.castling), None
+    piece = board[start]
+    if flag == "castle":
+        back_rank = 0 if white else 7
+        kingside = to > start
+        board[start] = board[to] = None
+        board[back_rank * 8 + (6 if kingside else 2)] = own("K", white)
+        board[back_rank * 8 + (5 if kingside else 3)] = own("R", white)
+        castling = {c for c in castling if c[0] != white}
+    else:
+        if flag == "ep":
+            board[to - 8 if white else to + 8] = None
+        board[to] = piece if flag in (None, "ep") else own(flag, white)
+        board[start] = None
+        if piece.upper() == "P" and abs(to - start) == 16:
+            ep = (to + start) // 2
+        if piece.upper() == "K":
+            castling = {c for c in castling if c[0] != white}
+        castling = {c for c in castling if c[1] not in (start, to)}
+    return Position(board, not white, castling, ep)
+
+
+def children(pos):
+    for move in pseudolegal(pos):
+        child = make_move(pos, move)
+        if not attacked(child.board, child.board.index(own("K", pos.white)), child.white):
+            yield child
+
+
+def perft(pos, depth):
+    if depth == 1:
+        return sum(1 for _ in children(pos))
+    return sum(perft(child, depth - 1) for child in children(pos))
+
+
+def start_position(number):
+    """FEN of Chess960 start position `number`, from 0 to 959, in Scharnagl's numbering where 518 is
+    the standard start position"""
+    rank = [None] * 8
+    number, light = divmod(number, 4)
+    rank[2 * light + 1] = "B"
+    number, dark = divmod(number, 4)
+    rank[2 * dark] = "B"
+    number, queen = divmod(number, 6)
+    rank[[f for f in range(8) if rank[f] is None][queen]] = "Q"
+    knights = [(0, 1), (0, 2), (0, 3), (0, 4), (1, 2), (1, 3), (1, 4), (2, 3), (2, 4), (3, 4)][number]
+    empty = [f for f in range(8) if rank[f] is None]
+    for idx in knights:
+        rank[empty[idx]] = "N"
+    left, king, right = [f for f in range(8) if rank[f] is None]
+    rank[left], rank[king], rank[right] = "R", "K", "R"
+    rights = "".join(chr(ord("A") + f) for f in (right, left))
+    back = "".join(rank)
+    return f"{back.lower()}/pppppppp/8/8/8/8/PPPPPPPP/{back} w {rights}{rights.lower()} - 0 1"
+
+
+if __name__ == "__main__":
+    if len(sys.argv) == 3:
+        max_depth = int(sys.argv[2])
+        for line in open(sys.argv[1]):
+            if not line.strip() or line.startswith("#"):
+                continue
+            fen, *counts = line.strip().split(" ;")
+            for entry in counts:
+                depth, nodes = entry.split()
+                depth = int(depth[1:])
+                if depth <= max_depth:
+                    found = perft(parse(fen), depth)
+                    print("ok" if found == int(nodes) else "MISMATCH", fen, depth, nodes, found, flush=True)
+    else:
+        for fen in map(start_position, range(960)):
+            pos = parse(fen)
+            print(fen + "".join(f" ;D{depth} {perft(pos, depth)}" for depth in range(1, 5)), flush=True)
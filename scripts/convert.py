@@ -1,20 +0,0 @@
-##########################################
-# convert pgn to uci
-##########################################
-import chess
-import chess.pgn
-
-pgn = open("game.pgn")
-
-game = chess.pgn.read_game(pgn)
-
-board = game.board()
-
-moves = "position fen " + board.fen() + " moves"
-
-for move in game.mainline_moves():
-    board.push(move)
-    print(moves)
-    moves += " " + move.uci()
-
-print(moves)
@@ -0,0 +1,169 @@
+use std::{
+    fs,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use crate::{
+    board::Board,
+    pgn::{eval_comment, parse_pgn, Game, Line, PgnMove},
+    search::{
+        lmr_table::LmrTable,
+        search::{start_search, CHECKMATE},
+        SearchLimits,
+    },
+    thread::ThreadData,
+    transposition::{TranspositionTable, TARGET_TABLE_SIZE_MB},
+    types::pieces::Color,
+    uci::{ENGINE_NAME, VERSION},
+};
+
+/// Nodes searched per position when `--nodes` isn't given
+pub const DEFAULT_NODES: u64 = 1_000_000;
+
+/// Centipawns a move may lose against the engine's choice before it is marked as a mistake
+const MISTAKE_MARGIN: i32 = 100;
+/// Centipawns a move may lose against the engine's choice before it is marked as a blunder
+const BLUNDER_MARGIN: i32 = 300;
+/// Scores are capped at this many centipawns when measuring how much a move lost, so a won
+/// position that stays won isn't punished for choosing a slower mate or a smaller advantage
+const SWING_CAP: i32 = 1000;
+
+const MISTAKE_NAG: u8 = 2;
+const BLUNDER_NAG: u8 = 4;
+
+/// Searches every position of every game in a PGN file and prints the games back out, with the
+/// evaluation after each move, and the engine's line alongside moves that lost too much
+pub fn analyze(path: &str, nodes: u64) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("couldn't read {path}: {e}"))?;
+    let mut games = parse_pgn(&text).map_err(|e| e.to_string())?;
+
+    let tt = TranspositionTable::new(TARGET_TABLE_SIZE_MB);
+    let lmr = LmrTable::new();
+    for game in &mut games {
+        annotate(game, nodes, &tt, &lmr);
+        println!("{game}");
+    }
+    Ok(())
+}
+
+/// Best line found for a position and its score from the side to move's point of view
+struct Analysis {
+    score: i32,
+    line: Line,
+    /// The search stopped before finishing its first iteration, so the score is only the static eval
+    cut_off: bool,
+}
+
+fn annotate(game: &mut Game, nodes: u64, tt: &TranspositionTable, lmr: &LmrTable) {
+    let halt = AtomicBool::new(false);
+    let pondering = AtomicBool::new(false);
+    let global_nodes = AtomicU64::new(0);
+    let mut td = ThreadData::new(&halt, &pondering, Vec::new(), 0, lmr, &global_nodes);
+    td.limits = SearchLimits { nodes: Some(nodes), ..Default::default() };
+    tt.clear();
+    game.set_tag("Annotator", &format!("{ENGINE_NAME} {VERSION}"));
+
+    let mut boards = game.mainline_positions().map(|(board, _)| board).collect::<Vec<_>>();
+    boards.push(game.mainline_positions().last().map_or(game.start, |(board, node)| board.make_move(node.m)));
+
+    let mut analyses = Vec::with_capacity(boards.len());
+    for (ply, board) in boards.iter().enumerate() {
+        // Repetitions are detected from the hash of every position reached after the first move
+        td.hash_history = boards[1..=ply].iter().map(|b| b.zobrist_hash).collect();
+        analyses.push(search_position(&mut td, *board, tt));
+        td.nodes.reset();
+        halt.store(false, Ordering::Relaxed);
+        tt.age_up();
+    }
+
+    for (ply, node) in game.mainline.moves.iter_mut().enumerate() {
+        let (before, after) = (&analyses[ply], &analyses[ply + 1]);
+        let next = boards[ply + 1];
+        if next.legal_moves().len() != 0 {
+            node.add_comment(&eval_comment(white_pov(after.score, next.stm)));
+        }
+
+        // Searches of consecutive positions don't always agree, so the engine's own choice is
+        // never marked as a mistake. A static eval says too little to judge the move either.
+        if before.cut_off || after.cut_off || before.line.moves.first().is_none_or(|best| best.m == node.m) {
+            continue;
+        }
+        // The played move is worth the negation of the score the opponent gets in reply
+        let lost = before.score.clamp(-SWING_CAP, SWING_CAP) - (-after.score).clamp(-SWING_CAP, SWING_CAP);
+        let nag = if lost >= BLUNDER_MARGIN {
+            BLUNDER_NAG
+        } else if lost >= MISTAKE_MARGIN {
+            MISTAKE_NAG
+        } else {
+            continue;
+        };
+        if !node.nags.contains(&nag) {
+            node.nags.push(nag);
+        }
+        node.variations.push(before.line.clone());
+    }
+}
+
+/// Searches a position, scoring positions without legal moves directly since there is nothing to
+/// search
+fn search_position(td: &mut ThreadData, board: Board, tt: &TranspositionTable) -> Analysis {
+    if board.legal_moves().len() == 0 {
+        let score = if board.in_check() { -CHECKMATE } else { 0 };
+        return Analysis { score, line: Line::default(), cut_off: false };
+    }
+
+    start_search(td, false, board, tt);
+    // Searches given too few nodes to finish their first iteration have no line to offer, only the
+    // static eval
+    let Some(best) = td.pv_lines.first() else {
+        let score = board.new_accumulator().scaled_evaluate(&board);
+        return Analysis { score, line: Line::default(), cut_off: true };
+    };
+    let mut moves = best.pv.line.iter().map_while(|m| m.map(PgnMove::new)).collect::<Vec<_>>();
+    if let Some(first) = moves.first_mut() {
+        first.add_comment(&eval_comment(white_pov(best.score, board.stm)));
+    }
+    Analysis { score: best.score, line: Line { comment: None, moves }, cut_off: false }
+}
+
+const fn white_pov(score: i32, stm: Color) -> i32 {
+    match stm {
+        Color::White => score,
+        Color::Black => -score,
+    }
+}
+
+#[cfg(test)]
+mod analyze_tests {
+    use super::{annotate, BLUNDER_NAG};
+    use crate::{pgn::parse_pgn, search::lmr_table::LmrTable, transposition::TranspositionTable};
+
+    #[test]
+    fn annotate_blunder() {
+        let mut game = parse_pgn("1. f3 e5 2. g4 Qh4# 0-1").unwrap().remove(0);
+        annotate(&mut game, 20_000, &TranspositionTable::new(1), &LmrTable::new());
+        let moves = &game.mainline.moves;
+
+        // Every move but the mate is followed by the evaluation it leaves behind
+        assert!(moves[..3].iter().all(|node| node.comment.as_ref().is_some_and(|c| c.starts_with("[%eval "))));
+        assert_eq!(moves[2].comment.as_deref(), Some("[%eval #-1]"));
+        assert_eq!(moves[3].comment, None);
+
+        // Allowing mate in one is a blunder, and the engine suggests something else
+        assert!(moves[2].nags.contains(&BLUNDER_NAG));
+        assert_eq!(moves[2].variations.len(), 1);
+        assert_ne!(moves[2].variations[0].moves[0].m, moves[2].m);
+        assert!(moves[3].nags.is_empty());
+    }
+
+    #[test]
+    fn cut_off_searches() {
+        let mut game = parse_pgn("1. f3 e5 2. g4 Qh4# 0-1").unwrap().remove(0);
+        annotate(&mut game, 1, &TranspositionTable::new(1), &LmrTable::new());
+        let moves = &game.mainline.moves;
+
+        // Static evals are still reported, but nothing is judged on them
+        assert!(moves[..2].iter().all(|node| node.comment.as_ref().is_some_and(|c| c.starts_with("[%eval "))));
+        assert!(moves.iter().all(|node| node.nags.is_empty() && node.variations.is_empty()));
+    }
+}
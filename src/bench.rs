@@ -1,36 +1,91 @@
 use std::{
-    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    collections::VecDeque,
+    fs,
+    sync::{
+        atomic::{AtomicBool, AtomicU64},
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    thread,
     time::Instant,
 };
 
 use crate::{
     board::Board,
-    search::{lmr_table::LmrTable, search::start_search, SearchType},
-    thread::ThreadData,
+    search::{lmr_table::LmrTable, SearchLimits},
+    thread::ThreadPool,
     transposition::{TranspositionTable, TARGET_TABLE_SIZE_MB},
+    uci::{GoCommand, UciError},
 };
 
-pub fn bench() {
-    let start = Instant::now();
+/// Settings for a bench run, given as `bench [depth] [threads] [hash] [fen-file]`. Anything left out
+/// keeps the value used for the node count signature of each commit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BenchConfig {
+    pub depth: i32,
+    pub threads: usize,
+    /// Size of the transposition table in MB
+    pub hash: usize,
+    /// File with one FEN per line to search instead of the built in positions
+    pub fen_file: Option<String>,
+}
 
-    let transpos_table = TranspositionTable::new(TARGET_TABLE_SIZE_MB);
-    let halt = AtomicBool::new(false);
-    let lmr = LmrTable::new();
-    let global_nodes = AtomicU64::new(0);
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self { depth: 14, threads: 1, hash: TARGET_TABLE_SIZE_MB, fen_file: None }
+    }
+}
 
-    let mut thread = ThreadData::new(&halt, Vec::new(), 0, &lmr, &global_nodes);
+impl BenchConfig {
+    fn positions(&self) -> Result<Vec<Board>, UciError> {
+        let Some(path) = &self.fen_file else {
+            return Ok(BENCH_POSITIONS.iter().map(|fen| Board::from_fen(fen)).collect());
+        };
+        let text = fs::read_to_string(path).map_err(|e| UciError::UnreadableFile(format!("{path}: {e}")))?;
+        text.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|fen| Board::try_from_fen(fen.trim()).map_err(UciError::InvalidFen))
+            .collect()
+    }
+}
 
-    thread.search_type = SearchType::Depth(14);
+/// Searches each bench position to a fixed depth with a fresh transposition table, printing the
+/// nodes searched for every position and finally the total, which serves as the signature of the
+/// search. The search runs on threads of its own, so the tables of the game in progress are left
+/// as they were.
+pub fn bench(
+    config: &BenchConfig,
+    thread_pool: &ThreadPool,
+    commands: &Receiver<String>,
+    pending: &mut VecDeque<String>,
+) -> Result<(), UciError> {
+    let positions = config.positions()?;
+    thread_pool.with_separate_pool(|pool| run(config, &positions, pool, commands, pending));
+    Ok(())
+}
 
-    let mut nodes = 0;
+/// Searches `positions` with `thread_pool`, whose threads are expected to start with empty tables
+/// so the node counts only depend on the engine
+fn run(
+    config: &BenchConfig,
+    positions: &[Board],
+    thread_pool: &mut ThreadPool,
+    commands: &Receiver<String>,
+    pending: &mut VecDeque<String>,
+) {
+    let start = Instant::now();
+
+    let transpos_table = Arc::new(TranspositionTable::new(config.hash));
+    thread_pool.add_workers(config.threads);
+    thread_pool.print_uci = false;
 
-    for fen in &BENCH_POSITIONS {
-        let board = Board::from_fen(fen);
-        start_search(&mut thread, false, board, &transpos_table);
-        nodes += thread.nodes.local_count();
-        transpos_table.age_up();
-        thread.nodes.reset();
-        halt.store(false, Ordering::Relaxed);
+    let go =
+        GoCommand { limits: SearchLimits { depth: Some(config.depth), ..Default::default() }, ..Default::default() };
+    let mut nodes = 0;
+    for (idx, board) in positions.iter().enumerate() {
+        thread_pool.handle_go(&go, board, commands, pending, &[], &transpos_table);
+        println!("position {}/{}: {} nodes {}", idx + 1, positions.len(), thread_pool.nodes(), board.to_fen());
+        nodes += thread_pool.nodes();
     }
 
     let time = start.elapsed().as_secs_f64();
@@ -38,6 +93,22 @@ pub fn bench() {
     println!("{} nodes {} nps", nodes, (nodes as f64 / time) as u64);
 }
 
+/// Runs a bench outside of the UCI loop, for `titan bench` on the command line
+pub fn standalone_bench(config: &BenchConfig) -> Result<(), UciError> {
+    let halt = AtomicBool::new(false);
+    let pondering = AtomicBool::new(false);
+    let lmr = LmrTable::new();
+    let global_nodes = AtomicU64::new(0);
+    // Nothing is ever sent, but the sender has to stay alive or the pool assumes stdin was closed
+    let (_sender, commands) = mpsc::channel();
+    let positions = config.positions()?;
+    thread::scope(|s| {
+        let mut thread_pool = ThreadPool::new(s, &halt, &pondering, &lmr, &global_nodes);
+        run(config, &positions, &mut thread_pool, &commands, &mut VecDeque::new());
+    });
+    Ok(())
+}
+
 const BENCH_POSITIONS: [&str; 50] = [
     "r3k2r/2pb1ppp/2pp1q2/p7/1nP1B3/1P2P3/P2N1PPP/R2QK2R w KQkq a6 0 14",
     "4rrk1/2p1b1p1/p1p3q1/4p3/2P2n1p/1P1NR2P/PB3PP1/3R1QK1 b - - 2 24",
@@ -6,7 +6,7 @@ use crate::{
     chess_move::{
         Castle,
         Direction::{North, South},
-        Move, MoveType, CASTLING_RIGHTS,
+        Move, MoveType,
     },
     magics::{bishop_attacks, queen_attacks, rook_attacks},
     types::{
@@ -25,6 +25,8 @@ pub struct Board {
     /// Side to move
     pub stm: Color,
     pub castling_rights: u32,
+    /// Starting squares of the rooks for each castle, indexed by `Castle::idx`
+    castling_rooks: [Square; 4],
     pub en_passant_square: Option<Square>,
     pub num_moves: u16,
     pub half_moves: u16,
@@ -104,9 +106,9 @@ impl Board {
         // Return hash right away if the move was a null move
         let Some(m) = m else { return hash };
 
-        hash ^= ZOBRIST.piece[self.piece_at(m.from())][m.from()] ^ ZOBRIST.piece[self.piece_at(m.from())][m.to()];
+        hash ^= ZOBRIST.piece[self.piece_at(m.from())][m.from()] ^ ZOBRIST.piece[self.piece_at(m.from())][m.dest()];
 
-        if self.piece_at(m.to()) != Piece::None {
+        if m.is_capture(self) {
             hash ^= ZOBRIST.piece[self.piece_at(m.to())][m.to()];
         }
 
@@ -115,7 +117,9 @@ impl Board {
 
     /// Returns the type of piece captured by a move, if any
     pub fn capture(&self, m: Move) -> Piece {
-        if m.is_en_passant() {
+        if m.is_castle() {
+            Piece::None
+        } else if m.is_en_passant() {
             Piece::new(PieceName::Pawn, !self.stm)
         } else {
             self.piece_at(m.to())
@@ -136,13 +140,50 @@ impl Board {
     }
 
     pub const fn can_castle(&self, c: Castle) -> bool {
-        match c {
-            Castle::WhiteKing => self.castling_rights & Castle::WhiteKing as u32 != 0,
-            Castle::WhiteQueen => self.castling_rights & Castle::WhiteQueen as u32 != 0,
-            Castle::BlackKing => self.castling_rights & Castle::BlackKing as u32 != 0,
-            Castle::BlackQueen => self.castling_rights & Castle::BlackQueen as u32 != 0,
-            Castle::None => panic!(),
+        self.castling_rights & c as u32 != 0
+    }
+
+    /// Square the rook castles from, which can be anywhere on the back rank in Chess960
+    pub const fn castling_rook(&self, c: Castle) -> Square {
+        self.castling_rooks[c.idx()]
+    }
+
+    pub(crate) fn set_castling_right(&mut self, c: Castle, rook: Square) {
+        self.castling_rights |= c as u32;
+        self.castling_rooks[c.idx()] = rook;
+    }
+
+    /// Checks that nothing stands between the king and rook and their castled squares, and that the
+    /// king doesn't pass through or land on an attacked square. Doesn't check whether the castle is
+    /// still available or whether the king is currently in check.
+    pub(crate) fn castle_path_is_safe(&self, c: Castle) -> bool {
+        let king = self.king_square(self.stm);
+        let rook = self.castling_rook(c);
+        let occ = self.occupancies() ^ king.bitboard() ^ rook.bitboard();
+        let king_path = between(king, c.king_to()) | c.king_to().bitboard();
+        let rook_path = between(rook, c.rook_to()) | c.rook_to().bitboard();
+
+        (king_path | rook_path) & occ == Bitboard::EMPTY
+            && king_path & self.threats() == Bitboard::EMPTY
+            // The castling rook may have been shielding the king's destination along the back rank
+            && self.attackers_for_side(!self.stm, c.king_to(), occ) == Bitboard::EMPTY
+    }
+
+    /// Castling rights that remain after a piece moves from or to the given squares
+    fn castling_rights_after(&self, from: Square, to: Square) -> u32 {
+        let mut rights = self.castling_rights;
+        if from == self.king_square(self.stm) {
+            rights &= match self.stm {
+                Color::White => !(Castle::WhiteKing as u32 | Castle::WhiteQueen as u32),
+                Color::Black => !(Castle::BlackKing as u32 | Castle::BlackQueen as u32),
+            };
         }
+        for c in [Castle::WhiteKing, Castle::WhiteQueen, Castle::BlackKing, Castle::BlackQueen] {
+            if self.castling_rook(c) == from || self.castling_rook(c) == to {
+                rights &= !(c as u32);
+            }
+        }
+        rights
     }
 
     pub fn place_piece(&mut self, piece: Piece, sq: Square) {
@@ -187,8 +228,7 @@ impl Board {
         (pawn_attacks | knight_attacks | bishop_attacks | rook_attacks | king_attacks) & self.color(attacker)
     }
 
-    #[expect(dead_code)]
-    fn square_under_attack(&self, attacker: Color, sq: Square) -> bool {
+    pub(crate) fn square_under_attack(&self, attacker: Color, sq: Square) -> bool {
         self.attackers_for_side(attacker, sq, self.occupancies()) != Bitboard::EMPTY
     }
 
@@ -279,6 +319,11 @@ impl Board {
                 && (rook_attacks(king, occ) & self.orthos(ntm)).is_empty();
         }
 
+        // Castles are fully checked when they are generated
+        if m.is_castle() {
+            return true;
+        }
+
         if moved_piece.name() == PieceName::King {
             return !self.threats().contains(to);
         }
@@ -318,33 +363,19 @@ impl Board {
             return false;
         }
 
-        if is_capture && (capture.color() == self.stm || capture.name() == PieceName::King) {
-            return false;
-        }
-
         if m.is_castle() {
-            if self.in_check() {
-                return false;
-            }
-            if moving.name() != PieceName::King {
+            if self.in_check() || moving.name() != PieceName::King {
                 return false;
             }
             let castle = m.castle_type();
-            if !self.can_castle(castle) {
-                return false;
-            }
-
-            if self.occupancies() & castle.empty_squares() != Bitboard::EMPTY {
-                return false;
-            }
-            if castle.check_squares() & self.threats() != Bitboard::EMPTY {
-                return false;
-            }
-            if self.piece_color(self.stm, PieceName::Rook) & castle.rook_from().bitboard() == Bitboard::EMPTY {
-                return false;
-            }
+            return castle.color() == self.stm
+                && self.can_castle(castle)
+                && self.castling_rook(castle) == to
+                && self.castle_path_is_safe(castle);
+        }
 
-            return true;
+        if is_capture && (capture.color() == self.stm || capture.name() == PieceName::King) {
+            return false;
         }
 
         match moving.name() {
@@ -391,20 +422,24 @@ impl Board {
         let mut board = *self;
         let piece_moving = board.piece_at(m.from());
         let capture = board.capture(m);
-        board.remove_piece(m.to());
 
-        if m.promotion().is_none() {
-            board.place_piece(piece_moving, m.to());
+        if m.is_castle() {
+            // The king and rook may swap squares or stay put in Chess960, so both are lifted off
+            // the board before either is placed
+            let castle = m.castle_type();
+            board.remove_piece(m.from());
+            board.remove_piece(m.to());
+            board.place_piece(piece_moving, castle.king_to());
+            board.place_piece(Piece::new(PieceName::Rook, board.stm), castle.rook_to());
+        } else {
+            board.remove_piece(m.to());
+            if m.promotion().is_none() {
+                board.place_piece(piece_moving, m.to());
+            }
+            board.remove_piece(m.from());
         }
 
-        board.remove_piece(m.from());
-
-        // Move rooks if a castle move is applied
-        if m.is_castle() {
-            let rook = Piece::new(PieceName::Rook, board.stm);
-            board.place_piece(rook, m.castle_type().rook_to());
-            board.remove_piece(m.castle_type().rook_from());
-        } else if let Some(p) = m.promotion() {
+        if let Some(p) = m.promotion() {
             board.place_piece(Piece::new(p, board.stm), m.to());
         } else if m.is_en_passant() {
             match board.stm {
@@ -452,7 +487,7 @@ impl Board {
         }
 
         board.zobrist_hash ^= ZOBRIST.castling[board.castling_rights as usize];
-        board.castling_rights &= CASTLING_RIGHTS[m.from()] & CASTLING_RIGHTS[m.to()];
+        board.castling_rights = self.castling_rights_after(m.from(), m.to());
         board.zobrist_hash ^= ZOBRIST.castling[board.castling_rights as usize];
 
         board.stm = !board.stm;
@@ -507,6 +542,7 @@ impl Board {
             color_occupancies: [Bitboard::EMPTY; 2],
             mailbox: [Piece::None; 64],
             castling_rights: 0,
+            castling_rooks: [Square::H1, Square::A1, Square::H8, Square::A8],
             stm: Color::White,
             en_passant_square: None,
             num_moves: 0,
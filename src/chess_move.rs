@@ -2,18 +2,22 @@ use core::fmt;
 use std::{
     fmt::Display,
     num::{NonZero, NonZeroU16},
+    sync::atomic::{AtomicBool, Ordering},
 };
 
 use crate::{
     board::Board,
     chess_move::Direction::{East, North, NorthEast, NorthWest, South, SouthEast, SouthWest, West},
     types::{
-        bitboard::Bitboard,
-        pieces::{Piece, PieceName},
+        pieces::{Color, Piece, PieceName},
         square::Square,
     },
 };
 
+/// Set by the `UCI_Chess960` option. Castles are always stored as the king capturing its own rook,
+/// but outside of Chess960 GUIs expect them to be written as the king moving two squares.
+pub static CHESS960: AtomicBool = AtomicBool::new(false);
+
 use MoveType::{
     BishopPromotion, CastleMove, DoublePush, EnPassant, KnightPromotion, Normal, QueenPromotion, RookPromotion,
 };
@@ -42,6 +46,7 @@ const _: () = assert!(std::mem::size_of::<Move>() == std::mem::size_of::<Option<
 /// bit 12-15: special move flag: normal move(0), promotion (1), en passant (2), castling (3)
 /// bit 16-19: piece moving - useful in continuation history
 /// NOTE: en passant bit is set only when a pawn can be captured
+/// NOTE: castles are encoded as the king moving to the square of the rook it castles with
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Move(pub NonZeroU16);
 
@@ -54,7 +59,7 @@ impl Move {
     }
 
     pub fn is_capture(self, board: &Board) -> bool {
-        board.occupancies().occupied(self.to())
+        !self.is_castle() && board.occupancies().occupied(self.to())
     }
 
     pub fn is_castle(self) -> bool {
@@ -92,17 +97,18 @@ impl Move {
     }
 
     pub fn is_tactical(self, board: &Board) -> bool {
-        self.promotion().is_some() || self.is_en_passant() || board.occupancies().occupied(self.to())
+        self.promotion().is_some() || self.is_en_passant() || self.is_capture(board)
     }
 
-    /// To Short Algebraic Notation
-    pub fn to_san(self) -> String {
+    /// To the long algebraic notation used by UCI, e.g. e2e4 or e7e8q
+    pub fn to_uci(self) -> String {
         let mut str = String::new();
         let arr = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        let to = if CHESS960.load(Ordering::Relaxed) { self.to() } else { self.dest() };
         let origin_number = self.from().rank() + 1;
         let origin_letter = self.from().file();
-        let end_number = self.to().rank() + 1;
-        let end_letter = self.to().file();
+        let end_number = to.rank() + 1;
+        let end_letter = to.file();
         str += arr[origin_letter as usize];
         str += &origin_number.to_string();
         str += arr[end_letter as usize];
@@ -119,25 +125,30 @@ impl Move {
         str
     }
 
+    /// Square the moving piece ends up on, which for castles isn't the square the move is encoded with
+    pub fn dest(self) -> Square {
+        if self.is_castle() {
+            self.castle_type().king_to()
+        } else {
+            self.to()
+        }
+    }
+
+    /// The king captures a rook on its right when castling kingside and on its left when castling
+    /// queenside
     pub fn castle_type(self) -> Castle {
         debug_assert!(self.is_castle());
-        if self.to().dist(self.from()) != 2 {
-            Castle::None
-        } else if self.to() == Square(2) {
-            Castle::WhiteQueen
-        } else if self.to() == Square(6) {
-            Castle::WhiteKing
-        } else if self.to() == Square(58) {
-            Castle::BlackQueen
-        } else if self.to() == Square(62) {
-            Castle::BlackKing
-        } else {
-            unreachable!()
+        let kingside = self.to().file() > self.from().file();
+        match (self.from().rank() == 0, kingside) {
+            (true, true) => Castle::WhiteKing,
+            (true, false) => Castle::WhiteQueen,
+            (false, true) => Castle::BlackKing,
+            (false, false) => Castle::BlackQueen,
         }
     }
 
-    /// Method converts a san move provided by UCI framework into a Move struct
-    pub fn from_san(str: &str, board: &Board) -> Self {
+    /// Method converts a long algebraic move provided by UCI framework into a Move struct
+    pub fn from_uci(str: &str, board: &Board) -> Self {
         let vec: Vec<char> = str.chars().collect();
 
         // Using base 20 allows program to convert letters directly to numbers instead of matching
@@ -148,7 +159,7 @@ impl Move {
 
         let end_column = vec[2].to_digit(20).unwrap() - 10;
         let end_row = (vec[3].to_digit(10).unwrap() - 1) * 8;
-        let dest_sq = Square(end_row + end_column);
+        let mut dest_sq = Square(end_row + end_column);
 
         let promotion = if vec.len() > 4 {
             match vec[4] {
@@ -164,25 +175,12 @@ impl Move {
         let piece_moving = board.piece_at(origin_sq);
         assert!(piece_moving != Piece::None);
         let captured = board.piece_at(dest_sq);
-        let castle = match piece_moving.name() {
-            PieceName::King => {
-                if origin_sq.dist(dest_sq) != 2 {
-                    Castle::None
-                } else if dest_sq == Square(2) {
-                    Castle::WhiteQueen
-                } else if dest_sq == Square(6) {
-                    Castle::WhiteKing
-                } else if dest_sq == Square(58) {
-                    Castle::BlackQueen
-                } else if dest_sq == Square(62) {
-                    Castle::BlackKing
-                } else {
-                    unreachable!()
-                }
-            }
-            _ => Castle::None,
-        };
-        let castle = castle != Castle::None;
+        // Castles may be written as the king capturing its rook, or as the king moving two squares
+        let castle = piece_moving.name() == PieceName::King
+            && (captured == Piece::new(PieceName::Rook, piece_moving.color()) || origin_sq.dist(dest_sq) == 2);
+        if castle && captured == Piece::None {
+            dest_sq = board.castling_rook(Move::new(origin_sq, dest_sq, CastleMove).castle_type());
+        }
         let en_passant =
             { piece_moving.name() == PieceName::Pawn && captured == Piece::None && start_column != end_column };
         let double_push = { piece_moving.name() == PieceName::Pawn && origin_sq.dist(dest_sq) == 2 };
@@ -224,7 +222,7 @@ impl From<Move> for u16 {
 impl Display for Move {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut str = String::new();
-        str += &self.to_san();
+        str += &self.to_uci();
         write!(f, "{str}")
     }
 }
@@ -232,7 +230,7 @@ impl Display for Move {
 impl fmt::Debug for Move {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut str = String::new();
-        str += &self.to_san();
+        str += &self.to_uci();
         write!(f, "{str}")
     }
 }
@@ -243,29 +241,28 @@ pub enum Castle {
     WhiteQueen = 2,
     BlackKing = 4,
     BlackQueen = 8,
-    None,
 }
 
 impl Castle {
-    /// These squares may not be under attack for a castle to be valid
-    pub(crate) const fn check_squares(self) -> Bitboard {
+    pub(crate) const fn color(self) -> Color {
         match self {
-            Self::WhiteKing => Bitboard(112),
-            Self::WhiteQueen => Bitboard(28),
-            Self::BlackKing => Bitboard(0x7000_0000_0000_0000),
-            Self::BlackQueen => Bitboard(0x1C00_0000_0000_0000),
-            Self::None => panic!("Invalid castle"),
+            Self::WhiteKing | Self::WhiteQueen => Color::White,
+            Self::BlackKing | Self::BlackQueen => Color::Black,
         }
     }
 
-    /// These squares must be unoccupied for a castle to be valid
-    pub(crate) const fn empty_squares(self) -> Bitboard {
+    /// Index of the castle in tables with one entry per castle
+    pub(crate) const fn idx(self) -> usize {
+        (self as u32).trailing_zeros() as usize
+    }
+
+    /// The king ends up on the g or c file no matter where it started
+    pub(crate) const fn king_to(self) -> Square {
         match self {
-            Self::WhiteKing => Bitboard(96),
-            Self::WhiteQueen => Bitboard(14),
-            Self::BlackKing => Bitboard(0x6000_0000_0000_0000),
-            Self::BlackQueen => Bitboard(0xE00_0000_0000_0000),
-            Self::None => panic!("Invalid castle"),
+            Self::WhiteKing => Square::G1,
+            Self::WhiteQueen => Square::C1,
+            Self::BlackKing => Square::G8,
+            Self::BlackQueen => Square::C8,
         }
     }
 
@@ -275,33 +272,10 @@ impl Castle {
             Self::WhiteQueen => Square::D1,
             Self::BlackKing => Square::F8,
             Self::BlackQueen => Square::D8,
-            Self::None => panic!("Invalid castle"),
-        }
-    }
-
-    pub(crate) const fn rook_from(self) -> Square {
-        match self {
-            Self::WhiteKing => Square::H1,
-            Self::WhiteQueen => Square::A1,
-            Self::BlackKing => Square::H8,
-            Self::BlackQueen => Square::A8,
-            Self::None => panic!("Invalid castle"),
         }
     }
 }
 
-#[rustfmt::skip]
-pub const CASTLING_RIGHTS: [u32; 64] = [
-    13, 15, 15, 15, 12, 15, 15, 14,
-    15, 15, 15, 15, 15, 15, 15, 15,
-    15, 15, 15, 15, 15, 15, 15, 15,
-    15, 15, 15, 15, 15, 15, 15, 15,
-    15, 15, 15, 15, 15, 15, 15, 15,
-    15, 15, 15, 15, 15, 15, 15, 15,
-    15, 15, 15, 15, 15, 15, 15, 15,
-    7,  15, 15, 15,  3, 15, 15, 11,
-];
-
 /// Cardinal directions from the point of view of white side
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Direction {
@@ -366,6 +340,20 @@ mod move_test {
         assert_eq!(en_passant_move.promotion(), None);
     }
 
+    #[test]
+    fn castle_notation() {
+        let board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        let m = Move::from_uci("e1g1", &board);
+        assert_eq!(m, Move::new(Square::E1, Square::H1, CastleMove));
+        assert_eq!(m, Move::from_uci("e1h1", &board));
+        assert_eq!(m.castle_type(), Castle::WhiteKing);
+        assert_eq!(Move::from_uci("e1c1", &board).castle_type(), Castle::WhiteQueen);
+        assert!(!m.is_capture(&board));
+
+        // Outside of Chess960 castles are written as the king moving two squares
+        assert_eq!(m.to_uci(), "e1g1");
+    }
+
     #[test]
     fn test_promotion_conversion() {
         let knight_promotion = Move::new(Square(0), Square(7), KnightPromotion);
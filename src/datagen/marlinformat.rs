@@ -0,0 +1,99 @@
+//! Positions are written in marlinformat, the 32 byte layout read by the bullet and marlinflow
+//! trainers. Every field is little endian.
+//!
+//! | bytes  | field                                                                         |
+//! |--------|-------------------------------------------------------------------------------|
+//! | 0..8   | occupancy bitboard, with a1 as the lowest bit                                 |
+//! | 8..24  | one nibble per occupied square in order of the occupancy, low nibble first    |
+//! | 24     | en passant square (64 if there is none), with the top bit set if black moves |
+//! | 25     | halfmove clock                                                                |
+//! | 26..28 | fullmove number                                                               |
+//! | 28..30 | score in centipawns from white's point of view                                |
+//! | 30     | result: 0 for a black win, 1 for a draw and 2 for a white win                 |
+//! | 31     | unused                                                                        |
+//!
+//! Each piece nibble holds the piece type in its low three bits, from 0 for a pawn to 5 for a king,
+//! or 6 for a rook that can still castle. The top bit of the nibble is set for black pieces.
+
+use crate::{
+    board::Board,
+    chess_move::Castle,
+    types::pieces::{Color, PieceName},
+};
+
+pub const PACKED_SIZE: usize = 32;
+
+/// Piece type of a rook that still has castling rights
+const UNMOVED_ROOK: u8 = 6;
+/// En passant square written when there is none
+const NO_EN_PASSANT: u8 = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Wdl {
+    BlackWin,
+    Draw,
+    WhiteWin,
+}
+
+/// Packs a position along with its score from white's point of view, the result of the game it was
+/// played in, and the full move number since `Board::num_moves` counts plies
+pub fn pack(board: &Board, score: i16, wdl: Wdl, fullmove: u16) -> [u8; PACKED_SIZE] {
+    let mut packed = [0; PACKED_SIZE];
+    let occupancy = board.occupancies();
+    packed[..8].copy_from_slice(&occupancy.0.to_le_bytes());
+
+    let castling_rooks = [Castle::WhiteKing, Castle::WhiteQueen, Castle::BlackKing, Castle::BlackQueen]
+        .into_iter()
+        .filter(|&c| board.can_castle(c))
+        .map(|c| board.castling_rook(c))
+        .collect::<Vec<_>>();
+    for (idx, sq) in occupancy.enumerate() {
+        let piece = board.piece_at(sq);
+        let mut nibble = match piece.name() {
+            PieceName::Rook if castling_rooks.contains(&sq) => UNMOVED_ROOK,
+            name => name as u8,
+        };
+        if piece.color() == Color::Black {
+            nibble |= 0b1000;
+        }
+        packed[8 + idx / 2] |= nibble << (4 * (idx % 2));
+    }
+
+    let en_passant = board.en_passant_square.map_or(NO_EN_PASSANT, |sq| sq.0 as u8);
+    packed[24] = en_passant | (u8::from(board.stm == Color::Black) << 7);
+    packed[25] = board.half_moves.min(u16::from(u8::MAX)) as u8;
+    packed[26..28].copy_from_slice(&fullmove.to_le_bytes());
+    packed[28..30].copy_from_slice(&score.to_le_bytes());
+    packed[30] = wdl as u8;
+    packed
+}
+
+#[cfg(test)]
+mod marlinformat_tests {
+    use super::{pack, Wdl};
+    use crate::{board::Board, fen::STARTING_FEN};
+
+    #[test]
+    fn starting_position() {
+        let packed = pack(&Board::from_fen(STARTING_FEN), -25, Wdl::Draw, 1);
+        assert_eq!(packed[..8], 0xFFFF_0000_0000_FFFF_u64.to_le_bytes());
+        // RNBQKBNR with castling rooks, eight white pawns, eight black pawns, then rnbqkbnr
+        assert_eq!(packed[8..24], [0x16, 0x42, 0x25, 0x61, 0, 0, 0, 0, 0x88, 0x88, 0x88, 0x88, 0x9E, 0xCA, 0xAD, 0xE9]);
+        assert_eq!(packed[24..], [64, 0, 1, 0, 0xE7, 0xFF, 1, 0]);
+    }
+
+    #[test]
+    fn side_to_move_and_en_passant() {
+        let board = Board::from_fen("4k3/8/8/8/3pP3/8/8/R3K3 b Q e3 0 40");
+        let packed = pack(&board, 150, Wdl::WhiteWin, 40);
+        assert_eq!(
+            u64::from_le_bytes(packed[..8].try_into().unwrap()),
+            (1 << 0) | (1 << 4) | (1 << 27) | (1 << 28) | (1 << 60)
+        );
+        // The a1 rook can still castle, the e1 king, d4 black pawn, e4 white pawn and e8 black king
+        assert_eq!(packed[8..11], [0x56, 0x08, 0x0D]);
+        // e3 is square 20, and black is to move
+        assert_eq!(packed[24], 20 | 0x80);
+        assert_eq!(packed[26..], [40, 0, 150, 0, 2, 0]);
+    }
+}
@@ -0,0 +1,304 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    board::Board,
+    epd::Epd,
+    fen::STARTING_FEN,
+    magics::Rng,
+    search::{lmr_table::LmrTable, search::start_search, SearchLimits},
+    thread::ThreadData,
+    tournament::flag_value,
+    transposition::TranspositionTable,
+    types::pieces::Color,
+};
+
+use self::marlinformat::{pack, Wdl};
+
+mod marlinformat;
+
+/// Size of the transposition table each thread searches with
+const HASH_MB: usize = 16;
+/// Games still going after this many plies are called a draw
+const MAX_GAME_PLIES: usize = 400;
+/// Once a search finds a score this large the side it favours is counted as the winner, since
+/// playing the rest of the game out wouldn't teach the net anything
+const WIN_SCORE: i32 = 2500;
+/// How often progress is reported
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+const USAGE: &str = "datagen <output> [--threads N] [--games N] [--nodes N] [--book <epd file>] [--random-plies N]";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DatagenConfig {
+    /// File positions are appended to
+    pub output: String,
+    pub threads: usize,
+    pub games: u32,
+    /// Nodes searched for every move
+    pub nodes: u64,
+    /// Positions games start from. Games start from the starting position without one.
+    pub book: Option<String>,
+    /// Random moves played from the opening before the engine takes over
+    pub random_plies: usize,
+}
+
+impl DatagenConfig {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let Some((output, args)) = args.split_first().filter(|(output, _)| !output.starts_with("--")) else {
+            return Err(format!("usage: {USAGE}"));
+        };
+        let mut config =
+            Self { output: output.clone(), threads: 1, games: 1000, nodes: 5000, book: None, random_plies: 8 };
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--threads" => config.threads = flag_value::<usize>(arg, iter.next())?.max(1),
+                "--games" => config.games = flag_value(arg, iter.next())?,
+                "--nodes" => config.nodes = flag_value(arg, iter.next())?,
+                "--book" => config.book = Some(flag_value(arg, iter.next())?),
+                "--random-plies" => config.random_plies = flag_value(arg, iter.next())?,
+                _ => return Err(format!("unknown argument {arg}\nusage: {USAGE}")),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Plays self-play games with a fixed number of nodes per move, appending every quiet position
+/// along with its score and the result of its game to the output in marlinformat
+pub fn run(args: &[String]) -> Result<(), String> {
+    let config = DatagenConfig::parse(args)?;
+    let openings = match &config.book {
+        Some(path) => {
+            let text = fs::read_to_string(path).map_err(|e| format!("couldn't read {path}: {e}"))?;
+            let openings = text
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| !line.trim().is_empty())
+                .map(|(idx, line)| {
+                    line.parse::<Epd>().map(|epd| epd.board).map_err(|e| format!("line {}: {e}", idx + 1))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if openings.is_empty() {
+                return Err(format!("{path} has no openings"));
+            }
+            openings
+        }
+        None => vec![Board::from_fen(STARTING_FEN)],
+    };
+    let file = File::options()
+        .create(true)
+        .append(true)
+        .open(&config.output)
+        .map_err(|e| format!("couldn't open {}: {e}", config.output))?;
+    let output = Mutex::new(BufWriter::new(file));
+
+    let lmr = LmrTable::new();
+    let next_game = AtomicU32::new(0);
+    let positions = AtomicU64::new(0);
+    let finished = AtomicBool::new(false);
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64);
+    let start = Instant::now();
+    let result = thread::scope(|s| {
+        let workers = (0..config.threads)
+            .map(|idx| {
+                let rng = Rng::with_seed(seed ^ (idx as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+                let (config, openings, lmr, output) = (&config, &openings, &lmr, &output);
+                let (next_game, positions) = (&next_game, &positions);
+                s.spawn(move || play_games(config, openings, lmr, rng, next_game, positions, output))
+            })
+            .collect::<Vec<_>>();
+
+        s.spawn(|| {
+            let mut last_report = Instant::now();
+            while !finished.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(100));
+                if last_report.elapsed() >= REPORT_INTERVAL {
+                    last_report = Instant::now();
+                    let games = next_game.load(Ordering::Relaxed).min(config.games);
+                    let count = positions.load(Ordering::Relaxed);
+                    let rate = count as f64 / start.elapsed().as_secs_f64();
+                    println!("{games}/{} games started, {count} positions, {rate:.0} positions/s", config.games);
+                }
+            }
+        });
+
+        let result = workers.into_iter().try_for_each(|worker| worker.join().expect("datagen thread panicked"));
+        finished.store(true, Ordering::Relaxed);
+        result
+    });
+    output.into_inner().expect("no thread panicked while writing").flush().map_err(|e| e.to_string())?;
+    result?;
+
+    println!(
+        "{} games, {} positions written to {} in {:.0} seconds",
+        config.games,
+        positions.load(Ordering::Relaxed),
+        config.output,
+        start.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
+
+/// Plays games until every game has been handed out to a thread
+fn play_games(
+    config: &DatagenConfig,
+    openings: &[Board],
+    lmr: &LmrTable,
+    mut rng: Rng,
+    next_game: &AtomicU32,
+    positions: &AtomicU64,
+    output: &Mutex<BufWriter<File>>,
+) -> Result<(), String> {
+    let tt = TranspositionTable::new(HASH_MB);
+    let halt = AtomicBool::new(false);
+    let pondering = AtomicBool::new(false);
+    // Threads play separate games, so each keeps its own node count for the node limit
+    let global_nodes = AtomicU64::new(0);
+    let mut td = ThreadData::new(&halt, &pondering, Vec::new(), 0, lmr, &global_nodes);
+    td.limits = SearchLimits { nodes: Some(config.nodes), ..Default::default() };
+
+    while next_game.fetch_add(1, Ordering::Relaxed) < config.games {
+        let (book, opening) = loop {
+            let book = openings[rng.next_u64() as usize % openings.len()];
+            if let Some(opening) = random_opening(book, config.random_plies, &mut rng) {
+                break (book, opening);
+            }
+        };
+        // The FEN counter of the book position is the only reliable move number to start from
+        let fullmove = book.num_moves + (config.random_plies as u16 + u16::from(book.stm == Color::Black)) / 2;
+        tt.clear();
+        let packed = play_game(&mut td, &tt, opening, fullmove);
+        positions.fetch_add(packed.len() as u64, Ordering::Relaxed);
+
+        let mut output = output.lock().expect("no thread panicked while writing");
+        for position in &packed {
+            output.write_all(position).map_err(|e| format!("couldn't write positions: {e}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Plays random moves from the opening, so that games don't all repeat each other. Gives up if the
+/// game ends before all of them have been played.
+fn random_opening(mut board: Board, plies: usize, rng: &mut Rng) -> Option<Board> {
+    for _ in 0..plies {
+        let moves = board.legal_moves();
+        if moves.len() == 0 {
+            return None;
+        }
+        board = board.make_move(moves[rng.next_u64() as usize % moves.len()]);
+    }
+    (board.legal_moves().len() != 0).then_some(board)
+}
+
+/// Plays a game against itself, returning the quiet positions of the game packed with their scores
+/// and the result
+fn play_game(
+    td: &mut ThreadData,
+    tt: &TranspositionTable,
+    opening: Board,
+    mut fullmove: u16,
+) -> Vec<[u8; marlinformat::PACKED_SIZE]> {
+    let mut board = opening;
+    let mut hashes = vec![board.zobrist_hash];
+    // Positions to keep along with their scores from white's point of view
+    let mut quiet = Vec::new();
+    let mut ply = 0;
+
+    let wdl = loop {
+        if board.legal_moves().len() == 0 {
+            break match (board.in_check(), board.stm) {
+                (false, _) => Wdl::Draw,
+                (true, Color::White) => Wdl::BlackWin,
+                (true, Color::Black) => Wdl::WhiteWin,
+            };
+        }
+        let repetitions = hashes.iter().filter(|&&hash| hash == board.zobrist_hash).count();
+        if board.is_draw() || repetitions >= 3 || ply >= MAX_GAME_PLIES {
+            break Wdl::Draw;
+        }
+
+        td.hash_history = hashes[1..].to_vec();
+        start_search(td, false, board, tt);
+        td.nodes.reset();
+        td.set_halt(false);
+        tt.age_up();
+        let m = td.best_move.expect("the position has legal moves");
+
+        // Searches cut off before finishing their first iteration don't have a score to record
+        if let Some(score) = td.pv_lines.first().map(|line| line.score) {
+            let white_score = if board.stm == Color::White { score } else { -score };
+            if score.abs() >= WIN_SCORE {
+                break if white_score > 0 { Wdl::WhiteWin } else { Wdl::BlackWin };
+            }
+            // The net can't see tactics being resolved, so only positions where the search agrees
+            // with a static evaluation are worth training on
+            if !board.in_check() && !m.is_tactical(&board) {
+                quiet.push((board, white_score as i16, fullmove));
+            }
+        }
+
+        if board.stm == Color::Black {
+            fullmove += 1;
+        }
+        board = board.make_move(m);
+        hashes.push(board.zobrist_hash);
+        ply += 1;
+    };
+
+    quiet.iter().map(|(board, score, fullmove)| pack(board, *score, wdl, *fullmove)).collect()
+}
+
+#[cfg(test)]
+mod datagen_tests {
+    use std::sync::atomic::{AtomicBool, AtomicU64};
+
+    use super::{marlinformat::PACKED_SIZE, play_game, random_opening, DatagenConfig};
+    use crate::{
+        board::Board,
+        fen::STARTING_FEN,
+        magics::Rng,
+        search::{lmr_table::LmrTable, SearchLimits},
+        thread::ThreadData,
+        transposition::TranspositionTable,
+        types::pieces::Color,
+    };
+
+    #[test]
+    fn parse_config() {
+        let args = ["data.bin", "--threads", "4", "--nodes", "1000"].map(ToString::to_string);
+        let config = DatagenConfig::parse(&args).unwrap();
+        assert_eq!((config.threads, config.nodes, config.random_plies), (4, 1000, 8));
+        assert!(DatagenConfig::parse(&args[1..]).is_err());
+        assert!(DatagenConfig::parse(&args[..2]).is_err());
+    }
+
+    #[test]
+    fn self_play() {
+        let mut rng = Rng::with_seed(7);
+        let board = random_opening(Board::from_fen(STARTING_FEN), 8, &mut rng).unwrap();
+        assert_eq!(board.stm, Color::White);
+
+        let (halt, pondering, global_nodes) = (AtomicBool::new(false), AtomicBool::new(false), AtomicU64::new(0));
+        let lmr = LmrTable::new();
+        let mut td = ThreadData::new(&halt, &pondering, Vec::new(), 0, &lmr, &global_nodes);
+        td.limits = SearchLimits { nodes: Some(1000), ..Default::default() };
+        let positions = play_game(&mut td, &TranspositionTable::new(1), board, 5);
+
+        assert!(!positions.is_empty());
+        // Every position of a game shares its result
+        assert!(positions.iter().all(|p| p[30] == positions[0][30] && p[30] <= 2));
+        assert!(positions.iter().all(|p| p.len() == PACKED_SIZE));
+        assert!(positions.iter().all(|p| u16::from_le_bytes([p[26], p[27]]) >= 5));
+    }
+}
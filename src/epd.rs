@@ -0,0 +1,292 @@
+use std::{
+    fmt, fs,
+    str::FromStr,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use crate::{
+    board::Board,
+    chess_move::Move,
+    fen::FenError,
+    san::SanError,
+    search::{
+        lmr_table::LmrTable,
+        search::{start_search, CHECKMATE, NEAR_CHECKMATE},
+        SearchLimits,
+    },
+    thread::ThreadData,
+    transposition::{TranspositionTable, TARGET_TABLE_SIZE_MB},
+};
+
+/// Reasons a line of an EPD file can be rejected
+#[derive(Debug, PartialEq, Eq)]
+pub enum EpdError {
+    InvalidFen(FenError),
+    /// A quoted operand is never closed
+    UnterminatedString,
+    InvalidMove {
+        opcode: String,
+        error: SanError,
+    },
+    InvalidOperand {
+        opcode: String,
+        operand: String,
+    },
+}
+
+impl fmt::Display for EpdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidFen(e) => write!(f, "invalid position: {e}"),
+            Self::UnterminatedString => write!(f, "string is never closed"),
+            Self::InvalidMove { opcode, error } => write!(f, "{opcode}: {error}"),
+            Self::InvalidOperand { opcode, operand } => write!(f, "invalid operand '{operand}' for {opcode}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Operation {
+    pub opcode: String,
+    /// Operands with the quotes around strings removed
+    pub operands: Vec<String>,
+}
+
+/// A position from an EPD file along with the operations describing it, such as `bm` for the best
+/// move or `id` for its name within a test suite
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Epd {
+    pub board: Board,
+    pub operations: Vec<Operation>,
+}
+
+impl Epd {
+    /// Operands of the first operation with the given opcode
+    pub fn operands(&self, opcode: &str) -> Option<&[String]> {
+        self.operations.iter().find(|op| op.opcode == opcode).map(|op| op.operands.as_slice())
+    }
+
+    /// First operand of an operation, for operations like `id` and `c0` that only take one
+    pub fn operand(&self, opcode: &str) -> Option<&str> {
+        self.operands(opcode).and_then(|operands| operands.first()).map(String::as_str)
+    }
+
+    /// Moves listed by an operation like `bm` or `am`, written in standard algebraic notation
+    pub fn moves(&self, opcode: &str) -> Result<Vec<Move>, EpdError> {
+        self.operands(opcode)
+            .unwrap_or_default()
+            .iter()
+            .map(|san| self.board.parse_san(san))
+            .collect::<Result<_, _>>()
+            .map_err(|error| EpdError::InvalidMove { opcode: opcode.to_string(), error })
+    }
+}
+
+impl FromStr for Epd {
+    type Err = EpdError;
+
+    /// An EPD line holds the first four fields of a FEN, followed by operations that each end with
+    /// a semicolon. The move counters missing from the FEN may be given by `hmvc` and `fmvn`.
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let line = line.trim_start();
+        let mut fields_end = 0;
+        for _ in 0..4 {
+            let field_start =
+                line[fields_end..].find(|c: char| !c.is_whitespace()).map_or(line.len(), |i| fields_end + i);
+            fields_end = line[field_start..].find(char::is_whitespace).map_or(line.len(), |i| field_start + i);
+        }
+        let operations = parse_operations(&line[fields_end..])?;
+
+        let counter = |opcode: &str, default: &str| {
+            operations
+                .iter()
+                .find(|op| op.opcode == opcode)
+                .and_then(|op| op.operands.first())
+                .map_or(default.to_string(), Clone::clone)
+        };
+        let fen = format!("{} {} {}", &line[..fields_end], counter("hmvc", "0"), counter("fmvn", "1"));
+        let board = Board::try_from_fen(&fen).map_err(EpdError::InvalidFen)?;
+        Ok(Self { board, operations })
+    }
+}
+
+fn parse_operations(text: &str) -> Result<Vec<Operation>, EpdError> {
+    let mut operations = Vec::new();
+    let mut tokens = Vec::<String>::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => {
+                if let Some((opcode, operands)) = tokens.split_first() {
+                    operations.push(Operation { opcode: opcode.clone(), operands: operands.to_vec() });
+                }
+                tokens.clear();
+            }
+            '"' => {
+                let mut str = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => str.extend(chars.next()),
+                        Some(c) => str.push(c),
+                        None => return Err(EpdError::UnterminatedString),
+                    }
+                }
+                tokens.push(str);
+            }
+            c if c.is_whitespace() => (),
+            c => {
+                let mut token = c.to_string();
+                while let Some(c) = chars.next_if(|&c| !c.is_whitespace() && c != ';' && c != '"') {
+                    token.push(c);
+                }
+                tokens.push(token);
+            }
+        }
+    }
+    // The semicolon after the last operation is often left out
+    if let Some((opcode, operands)) = tokens.split_first() {
+        operations.push(Operation { opcode: opcode.clone(), operands: operands.to_vec() });
+    }
+    Ok(operations)
+}
+
+/// What a test suite expects of the engine in a position
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Expectation {
+    /// One of these moves must be played
+    best: Vec<Move>,
+    /// None of these moves may be played
+    avoid: Vec<Move>,
+    /// Mate must be found in at most this many moves
+    mate: Option<i32>,
+}
+
+impl Expectation {
+    fn new(epd: &Epd) -> Result<Option<Self>, EpdError> {
+        let mate = match epd.operand("dm") {
+            Some(n) => Some(
+                n.parse().map_err(|_| EpdError::InvalidOperand { opcode: "dm".to_string(), operand: n.to_string() })?,
+            ),
+            None => None,
+        };
+        let expectation = Self { best: epd.moves("bm")?, avoid: epd.moves("am")?, mate };
+        Ok((expectation != Self::default()).then_some(expectation))
+    }
+
+    /// Whether a search that chose `m` with `score` meets every expectation
+    fn is_met(&self, m: Move, score: i32) -> bool {
+        (self.best.is_empty() || self.best.contains(&m))
+            && !self.avoid.contains(&m)
+            && self.mate.is_none_or(|n| score >= NEAR_CHECKMATE && (CHECKMATE - score + 1) / 2 <= n)
+    }
+}
+
+/// Searches every position of an EPD test suite such as WAC, STS or ERET, reporting whether the
+/// engine met the `bm`, `am` and `dm` operations of each position and how many it passed in total
+pub fn run_suite(path: &str, limits: SearchLimits) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("couldn't read {path}: {e}"))?;
+    let mut positions = Vec::new();
+    for (idx, line) in text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        let error = |e: EpdError| format!("line {}: {e}", idx + 1);
+        let epd = line.parse::<Epd>().map_err(error)?;
+        let expectation = Expectation::new(&epd).map_err(error)?;
+        positions.push((idx + 1, epd, expectation));
+    }
+
+    let tt = TranspositionTable::new(TARGET_TABLE_SIZE_MB);
+    let halt = AtomicBool::new(false);
+    let pondering = AtomicBool::new(false);
+    let lmr = LmrTable::new();
+    let global_nodes = AtomicU64::new(0);
+    let mut td = ThreadData::new(&halt, &pondering, Vec::new(), 0, &lmr, &global_nodes);
+    td.limits = limits;
+
+    let (mut passed, mut tested) = (0, 0);
+    for (line, epd, expectation) in &positions {
+        let name = epd.operand("id").map_or_else(|| format!("line {line}"), ToString::to_string);
+        let Some(expectation) = expectation else {
+            println!("{name}: skipped, no bm, am or dm operation");
+            continue;
+        };
+        if epd.board.legal_moves().len() == 0 {
+            println!("{name}: skipped, no legal moves");
+            continue;
+        }
+
+        tt.clear();
+        start_search(&mut td, false, epd.board, &tt);
+        td.nodes.reset();
+        halt.store(false, Ordering::Relaxed);
+
+        tested += 1;
+        let Some((m, score)) = td.pv_lines.first().and_then(|line| Some((line.first_move()?, line.score))) else {
+            println!("{name}: FAIL, search cut off before depth 1");
+            continue;
+        };
+        let met = expectation.is_met(m, score);
+        passed += usize::from(met);
+
+        let mut report = format!("{name}: {} {}", if met { "pass" } else { "FAIL" }, epd.board.san(m));
+        for opcode in ["bm", "am", "dm"] {
+            if let Some(operands) = epd.operands(opcode) {
+                report += &format!(" {opcode} {}", operands.join(" "));
+            }
+        }
+        if let Some(comment) = epd.operand("c0") {
+            report += &format!(" \"{comment}\"");
+        }
+        println!("{report}");
+    }
+
+    println!("passed {passed}/{tested}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod epd_tests {
+    use super::{Epd, EpdError, Expectation, Operation};
+    use crate::{chess_move::Move, search::search::CHECKMATE};
+
+    #[test]
+    fn parse_operations() {
+        let epd = "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id \"WAC.001\"; c0 \"a; b\";"
+            .parse::<Epd>()
+            .unwrap();
+        assert_eq!(epd.board.to_fen(), "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 1");
+        assert_eq!(epd.operand("id"), Some("WAC.001"));
+        assert_eq!(epd.operand("c0"), Some("a; b"));
+        assert_eq!(epd.moves("bm"), Ok(vec![Move::from_uci("g3g6", &epd.board)]));
+        assert_eq!(epd.moves("am"), Ok(Vec::new()));
+
+        let epd = "4k3/8/8/8/8/8/8/4K2R w K - am Rh8+ Kd1 ; hmvc 12; fmvn 40".parse::<Epd>().unwrap();
+        assert_eq!(epd.board.to_fen(), "4k3/8/8/8/8/8/8/4K2R w K - 12 40");
+        assert_eq!(
+            epd.operations[0],
+            Operation { opcode: "am".to_string(), operands: vec!["Rh8+".into(), "Kd1".into()] }
+        );
+        assert_eq!(epd.operations.len(), 3);
+    }
+
+    #[test]
+    fn malformed_epd() {
+        assert!(matches!("8/8/8 w - -".parse::<Epd>(), Err(EpdError::InvalidFen(_))));
+        assert_eq!("4k3/8/8/8/8/8/8/4K3 w - - id \"open".parse::<Epd>(), Err(EpdError::UnterminatedString));
+        let epd = "4k3/8/8/8/8/8/8/4K3 w - - bm Qh5;".parse::<Epd>().unwrap();
+        assert!(matches!(epd.moves("bm"), Err(EpdError::InvalidMove { .. })));
+    }
+
+    #[test]
+    fn expectations() {
+        let epd = "6k1/5ppp/8/8/8/8/8/R5K1 w - - bm Ra8#; am Kf1; dm 1;".parse::<Epd>().unwrap();
+        let expectation = Expectation::new(&epd).unwrap().unwrap();
+        let mate = Move::from_uci("a1a8", &epd.board);
+        assert!(expectation.is_met(mate, CHECKMATE - 1));
+        // The right move is only enough if the mate was actually seen
+        assert!(!expectation.is_met(mate, 500));
+        assert!(!expectation.is_met(Move::from_uci("g1f1", &epd.board), CHECKMATE - 1));
+
+        let epd = "6k1/5ppp/8/8/8/8/8/R5K1 w - - id \"nothing to test\";".parse::<Epd>().unwrap();
+        assert_eq!(Expectation::new(&epd), Ok(None));
+    }
+}
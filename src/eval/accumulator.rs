@@ -10,8 +10,9 @@ use crate::{
 };
 
 use super::{
+    net,
     network::{flatten, Network, BUCKETS, NORMALIZATION_FACTOR, NUM_BUCKETS, QAB, SCALE},
-    Align64, Block, NET,
+    Align64, Block,
 };
 use arrayvec::ArrayVec;
 use std::{
@@ -32,7 +33,7 @@ pub struct Accumulator {
 impl Default for Accumulator {
     fn default() -> Self {
         Self {
-            vals: [NET.feature_bias; 2],
+            vals: [net().feature_bias; 2],
             correct: [true; 2],
             m: Move(NonZeroU16::new(1).unwrap()),
             piece: Piece::None,
@@ -56,17 +57,19 @@ impl IndexMut<Color> for Accumulator {
 }
 
 impl Accumulator {
-    pub fn raw_evaluate(&self, stm: Color) -> i32 {
-        let (us, them) = (&self[stm], &self[!stm]);
-        let weights = &NET.output_weights;
+    pub fn raw_evaluate(&self, board: &Board) -> i32 {
+        let (us, them) = (&self[board.stm], &self[!board.stm]);
+        let net = net();
+        let bucket = Network::output_bucket(board.occupancies().count_bits());
+        let weights = &net.output_weights[bucket];
         let output = flatten(us, &weights[0]) + flatten(them, &weights[1]);
-        ((i32::from(NET.output_bias) + output / NORMALIZATION_FACTOR) * SCALE / QAB)
+        ((i32::from(net.output_bias[bucket]) + output / NORMALIZATION_FACTOR) * SCALE / QAB)
             .clamp(-NEAR_CHECKMATE + 1, NEAR_CHECKMATE - 1)
     }
 
     /// Credit to viridithas for these values and concepts
     pub fn scaled_evaluate(&self, board: &Board) -> i32 {
-        let raw = self.raw_evaluate(board.stm);
+        let raw = self.raw_evaluate(board);
         let eval = raw * board.mat_scale() / 1024;
         let eval = eval * (200 - board.half_moves as i32) / 200;
         (eval).clamp(-NEAR_CHECKMATE, NEAR_CHECKMATE)
@@ -77,15 +80,12 @@ impl Accumulator {
         unsafe {
             self.avx512_add_sub(old, a1, s1, side);
         }
-        #[cfg(not(feature = "avx512"))]
-        {
-            let weights = &NET.feature_weights;
-            self[side].iter_mut().zip(&weights[a1].0).zip(&weights[s1].0).zip(old[side].iter()).for_each(
-                |(((i, &a), &s), &o)| {
-                    *i = o + a - s;
-                },
-            );
+        #[cfg(all(not(feature = "avx512"), target_feature = "avx2"))]
+        unsafe {
+            self.avx2_add_sub(old, a1, s1, side);
         }
+        #[cfg(all(not(feature = "avx512"), not(target_feature = "avx2")))]
+        self.scalar_add_sub(old, a1, s1, side);
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -94,19 +94,12 @@ impl Accumulator {
         unsafe {
             self.avx512_add_sub_sub(old, a1, s1, s2, side);
         }
-        #[cfg(not(feature = "avx512"))]
-        {
-            let weights = &NET.feature_weights;
-            self[side]
-                .iter_mut()
-                .zip(&weights[a1].0)
-                .zip(&weights[s1].0)
-                .zip(&weights[s2].0)
-                .zip(old[side].iter())
-                .for_each(|((((i, &a), &s1), &s2), &o)| {
-                    *i = o + a - s1 - s2;
-                });
+        #[cfg(all(not(feature = "avx512"), target_feature = "avx2"))]
+        unsafe {
+            self.avx2_add_sub_sub(old, a1, s1, s2, side);
         }
+        #[cfg(all(not(feature = "avx512"), not(target_feature = "avx2")))]
+        self.scalar_add_sub_sub(old, a1, s1, s2, side);
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -115,37 +108,79 @@ impl Accumulator {
         unsafe {
             self.avx512_add_add_sub_sub(old, a1, a2, s1, s2, side);
         }
-        #[cfg(not(feature = "avx512"))]
-        {
-            let weights = &NET.feature_weights;
-            self[side]
-                .iter_mut()
-                .zip(&weights[a1].0)
-                .zip(&weights[a2].0)
-                .zip(&weights[s1].0)
-                .zip(&weights[s2].0)
-                .zip(old[side].iter())
-                .for_each(|(((((i, &a1), &a2), &s1), &s2), &o)| {
-                    *i = o + a1 + a2 - s1 - s2;
-                });
+        #[cfg(all(not(feature = "avx512"), target_feature = "avx2"))]
+        unsafe {
+            self.avx2_add_add_sub_sub(old, a1, a2, s1, s2, side);
         }
+        #[cfg(all(not(feature = "avx512"), not(target_feature = "avx2")))]
+        self.scalar_add_add_sub_sub(old, a1, a2, s1, s2, side);
+    }
+
+    // The scalar updates are kept around on SIMD builds, where tests check the SIMD versions against them
+    #[cfg_attr(any(feature = "avx512", target_feature = "avx2"), allow(dead_code))]
+    pub(super) fn scalar_add_sub(&mut self, old: &Accumulator, a1: usize, s1: usize, side: Color) {
+        let weights = &net().feature_weights;
+        self[side].iter_mut().zip(&weights[a1].0).zip(&weights[s1].0).zip(old[side].iter()).for_each(
+            |(((i, &a), &s), &o)| {
+                *i = o + a - s;
+            },
+        );
+    }
+
+    #[cfg_attr(any(feature = "avx512", target_feature = "avx2"), allow(dead_code))]
+    pub(super) fn scalar_add_sub_sub(&mut self, old: &Accumulator, a1: usize, s1: usize, s2: usize, side: Color) {
+        let weights = &net().feature_weights;
+        self[side]
+            .iter_mut()
+            .zip(&weights[a1].0)
+            .zip(&weights[s1].0)
+            .zip(&weights[s2].0)
+            .zip(old[side].iter())
+            .for_each(|((((i, &a), &s1), &s2), &o)| {
+                *i = o + a - s1 - s2;
+            });
+    }
+
+    #[cfg_attr(any(feature = "avx512", target_feature = "avx2"), allow(dead_code))]
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn scalar_add_add_sub_sub(
+        &mut self,
+        old: &Accumulator,
+        a1: usize,
+        a2: usize,
+        s1: usize,
+        s2: usize,
+        side: Color,
+    ) {
+        let weights = &net().feature_weights;
+        self[side]
+            .iter_mut()
+            .zip(&weights[a1].0)
+            .zip(&weights[a2].0)
+            .zip(&weights[s1].0)
+            .zip(&weights[s2].0)
+            .zip(old[side].iter())
+            .for_each(|(((((i, &a1), &a2), &s1), &s2), &o)| {
+                *i = o + a1 + a2 - s1 - s2;
+            });
     }
 
     pub(crate) fn lazy_update(&mut self, old: &Accumulator, side: Color, board: &Board) {
         let m = self.m;
         let piece_moving = self.piece;
 
+        let dest = m.dest();
         let from = if side == Color::Black { m.from().flip_vertical() } else { m.from() };
-        let to = if side == Color::Black { m.to().flip_vertical() } else { m.to() };
+        let to = if side == Color::Black { dest.flip_vertical() } else { dest };
         assert!(piece_moving.name() != PieceName::King || piece_moving.color() != side || BUCKETS[from] == BUCKETS[to]);
         let possible_promo = m.promotion().map_or(piece_moving, |promo| Piece::new(promo, piece_moving.color()));
         let king = board.king_square(side);
-        let a1 = Network::feature_idx(possible_promo, m.to(), king, side);
+        let a1 = Network::feature_idx(possible_promo, dest, king, side);
         let s1 = Network::feature_idx(piece_moving, m.from(), king, side);
         if m.is_castle() {
             let rook = Piece::new(PieceName::Rook, piece_moving.color());
             let a2 = Network::feature_idx(rook, m.castle_type().rook_to(), king, side);
-            let s2 = Network::feature_idx(rook, m.castle_type().rook_from(), king, side);
+            let s2 = Network::feature_idx(rook, m.to(), king, side);
 
             self.add_add_sub_sub(old, a1, a2, s1, s2, side);
         } else if self.capture != Piece::None || m.is_en_passant() {
@@ -167,9 +202,19 @@ impl Accumulator {
     }
 }
 
+pub fn update(acc: &mut Align64<Block>, adds: &[usize], subs: &[usize]) {
+    #[cfg(all(not(feature = "avx512"), target_feature = "avx2"))]
+    unsafe {
+        super::simd::avx2::update(acc, adds, subs);
+    }
+    #[cfg(any(feature = "avx512", not(target_feature = "avx2")))]
+    scalar_update(acc, adds, subs);
+}
+
 // Credit to akimbo. This function streamlines the assembly generated and prevents unnecessary
 // redundant loads and stores to the same simd vectors.
-pub fn update(acc: &mut Align64<Block>, adds: &[usize], subs: &[usize]) {
+#[cfg_attr(all(not(feature = "avx512"), target_feature = "avx2"), allow(dead_code))]
+pub(super) fn scalar_update(acc: &mut Align64<Block>, adds: &[usize], subs: &[usize]) {
     const REGISTERS: usize = 8;
     const ELEMENTS_PER_LOOP: usize = REGISTERS * 256 / 16;
 
@@ -183,7 +228,7 @@ pub fn update(acc: &mut Align64<Block>, adds: &[usize], subs: &[usize]) {
         }
 
         for &add in adds {
-            let weights = &NET.feature_weights[add];
+            let weights = &net().feature_weights[add];
 
             for (reg, &w) in regs.iter_mut().zip(weights[offset..].iter()) {
                 *reg += w;
@@ -191,7 +236,7 @@ pub fn update(acc: &mut Align64<Block>, adds: &[usize], subs: &[usize]) {
         }
 
         for &sub in subs {
-            let weights = &NET.feature_weights[sub];
+            let weights = &net().feature_weights[sub];
 
             for (reg, &w) in regs.iter_mut().zip(weights[offset..].iter()) {
                 *reg -= w;
@@ -208,7 +253,7 @@ impl Board {
     pub fn new_accumulator(&self) -> Accumulator {
         let mut acc = Accumulator::default();
         for view in Color::iter() {
-            acc.vals[view] = NET.feature_bias;
+            acc.vals[view] = net().feature_bias;
             let mut vec: ArrayVec<usize, 32> = ArrayVec::new();
             for sq in self.occupancies() {
                 let p = self.piece_at(sq);
@@ -264,7 +309,7 @@ impl AccumulatorStack {
             let piece_moving = self.stack[curr].piece;
 
             let from = if side == Color::Black { m.from().flip_vertical() } else { m.from() };
-            let to = if side == Color::Black { m.to().flip_vertical() } else { m.to() };
+            let to = if side == Color::Black { m.dest().flip_vertical() } else { m.dest() };
 
             if piece_moving.color() == side && piece_moving.name() == PieceName::King && BUCKETS[from] != BUCKETS[to] {
                 return false;
@@ -321,7 +366,7 @@ struct TableEntry {
 
 impl Default for TableEntry {
     fn default() -> Self {
-        Self { acc: NET.feature_bias, pieces: [Bitboard::EMPTY; 6], color: [Bitboard::EMPTY; 2] }
+        Self { acc: net().feature_bias, pieces: [Bitboard::EMPTY; 6], color: [Bitboard::EMPTY; 2] }
     }
 }
 
@@ -367,7 +412,7 @@ mod acc_test {
 
     macro_rules! make_move_nnue {
         ($board:ident, $stack:ident, $mv_str:literal) => {{
-            let m = Move::from_san($mv_str, &$board);
+            let m = Move::from_uci($mv_str, &$board);
             $stack.push(m, $board.piece_at(m.from()), $board.capture(m));
             assert!($board.is_legal(m));
             $board = $board.make_move(m);
@@ -391,6 +436,15 @@ mod acc_test {
         assert_correct!(board, stack);
     }
 
+    #[test]
+    fn chess960_castles() {
+        let mut board = Board::from_fen("rk5r/pppppppp/8/8/8/8/PPPPPPPP/RK5R w AHah - 0 1");
+        let mut stack = AccumulatorStack::new(board.new_accumulator());
+        make_move_nnue!(board, stack, "b1a1");
+        make_move_nnue!(board, stack, "b8h8");
+        assert_correct!(board, stack);
+    }
+
     #[test]
     fn deeper_error() {
         let mut board = Board::from_fen("8/8/1p2k1p1/3p3p/1p1P1P1P/1P2PK2/8/8 w - - 3 54");
@@ -0,0 +1,271 @@
+//! Net files start with a header describing the net stored after it, so that a net trained for a
+//! different architecture is rejected instead of silently evaluating garbage. Every field is little
+//! endian.
+//!
+//! | bytes   | field                                                      |
+//! |---------|------------------------------------------------------------|
+//! | 0..8    | magic, `TITANNET`                                          |
+//! | 8..12   | format version                                             |
+//! | 12..16  | input features per king bucket                             |
+//! | 16..20  | hidden layer size                                          |
+//! | 20..24  | number of king buckets                                     |
+//! | 24..28  | QA                                                         |
+//! | 28..32  | QB                                                         |
+//! | 32..40  | FNV-1a hash of the weights                                 |
+//! | 40..44  | number of output buckets                                   |
+//! | 44..64  | unused                                                     |
+//! | 64..128 | king bucket of each square, from a1 to h8                  |
+//!
+//! The weights follow the header in the layout of `Network`. Version 1 headers predate output
+//! buckets, and are read as describing nets with a single one.
+//!
+//! This file doesn't depend on the rest of the crate, since the build script uses it to check the
+//! net it embeds.
+
+// Errors describe both architectures in full, which is fine since one is built at most once per net
+#![allow(clippy::result_large_err)]
+
+use std::fmt;
+
+pub const MAGIC: [u8; 8] = *b"TITANNET";
+pub const FORMAT_VERSION: u32 = 2;
+pub const HEADER_SIZE: usize = 128;
+
+const BUCKETS_OFFSET: usize = 64;
+
+/// Everything that has to match between the trainer and the engine for a net to make sense
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Architecture {
+    pub input_size: u32,
+    pub hidden_size: u32,
+    pub num_buckets: u32,
+    pub qa: i32,
+    pub qb: i32,
+    pub buckets: [u8; 64],
+    pub output_buckets: u32,
+}
+
+impl Architecture {
+    const fn matches(&self, other: &Self) -> bool {
+        let mut sq = 0;
+        while sq < self.buckets.len() {
+            if self.buckets[sq] != other.buckets[sq] {
+                return false;
+            }
+            sq += 1;
+        }
+        self.input_size == other.input_size
+            && self.hidden_size == other.hidden_size
+            && self.num_buckets == other.num_buckets
+            && self.qa == other.qa
+            && self.qb == other.qb
+            && self.output_buckets == other.output_buckets
+    }
+}
+
+impl fmt::Display for Architecture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "({}x{}->{})x2->1x{} with QA {} and QB {}",
+            self.input_size, self.num_buckets, self.hidden_size, self.output_buckets, self.qa, self.qb
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NetHeader {
+    pub version: u32,
+    pub arch: Architecture,
+    /// Hash of the weights following the header
+    pub hash: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FormatError {
+    /// The file doesn't start with the magic, which is the case for nets stored before the header
+    /// existed
+    MissingHeader,
+    UnsupportedVersion(u32),
+    WrongArchitecture {
+        expected: Architecture,
+        found: Architecture,
+    },
+    /// The weights aren't the size the architecture calls for
+    WrongSize {
+        expected: usize,
+        found: usize,
+    },
+    /// The weights don't hash to the value in the header, so the file was corrupted
+    HashMismatch,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingHeader => write!(f, "net has no header, it can be added with the convertnet command"),
+            Self::UnsupportedVersion(v) => write!(f, "net format version {v} isn't supported"),
+            Self::WrongArchitecture { expected, found } if expected.buckets != found.buckets => {
+                write!(f, "net uses a different king bucket layout than this build")
+            }
+            Self::WrongArchitecture { expected, found } => {
+                write!(f, "net is {found}, but this build evaluates with {expected}")
+            }
+            Self::WrongSize { expected, found } => write!(f, "net weights are {found} bytes, expected {expected}"),
+            Self::HashMismatch => write!(f, "net weights don't match the hash in the header"),
+        }
+    }
+}
+
+impl NetHeader {
+    pub fn new(arch: Architecture, weights: &[u8]) -> Self {
+        Self { version: FORMAT_VERSION, arch, hash: hash(weights) }
+    }
+
+    /// Reads the header at the start of a net file, returning it along with the weights that
+    /// follow it
+    pub const fn parse(bytes: &[u8]) -> Result<(Self, &[u8]), FormatError> {
+        let Some((header, weights)) = bytes.split_first_chunk::<HEADER_SIZE>() else {
+            return Err(FormatError::MissingHeader);
+        };
+        let mut idx = 0;
+        while idx < MAGIC.len() {
+            if header[idx] != MAGIC[idx] {
+                return Err(FormatError::MissingHeader);
+            }
+            idx += 1;
+        }
+        let version = read_u32(header, 8);
+        if version == 0 || version > FORMAT_VERSION {
+            return Err(FormatError::UnsupportedVersion(version));
+        }
+
+        let mut buckets = [0; 64];
+        let mut sq = 0;
+        while sq < buckets.len() {
+            buckets[sq] = header[BUCKETS_OFFSET + sq];
+            sq += 1;
+        }
+        let arch = Architecture {
+            input_size: read_u32(header, 12),
+            hidden_size: read_u32(header, 16),
+            num_buckets: read_u32(header, 20),
+            qa: read_u32(header, 24) as i32,
+            qb: read_u32(header, 28) as i32,
+            buckets,
+            output_buckets: if version == 1 { 1 } else { read_u32(header, 40) },
+        };
+        let hash = u64::from_le_bytes([
+            header[32], header[33], header[34], header[35], header[36], header[37], header[38], header[39],
+        ]);
+        Ok((Self { version, arch, hash }, weights))
+    }
+
+    pub fn to_bytes(self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0; HEADER_SIZE];
+        bytes[..8].copy_from_slice(&MAGIC);
+        bytes[8..12].copy_from_slice(&self.version.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.arch.input_size.to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.arch.hidden_size.to_le_bytes());
+        bytes[20..24].copy_from_slice(&self.arch.num_buckets.to_le_bytes());
+        bytes[24..28].copy_from_slice(&self.arch.qa.to_le_bytes());
+        bytes[28..32].copy_from_slice(&self.arch.qb.to_le_bytes());
+        bytes[32..40].copy_from_slice(&self.hash.to_le_bytes());
+        bytes[40..44].copy_from_slice(&self.arch.output_buckets.to_le_bytes());
+        bytes[BUCKETS_OFFSET..].copy_from_slice(&self.arch.buckets);
+        bytes
+    }
+
+    /// Checks the header describes a net of the expected architecture, whose weights take up `size`
+    /// bytes. Hashing the weights is left to `check_hash`, since it is far too slow to run while
+    /// compiling.
+    pub const fn check_architecture(
+        &self,
+        expected: &Architecture,
+        weights: &[u8],
+        size: usize,
+    ) -> Result<(), FormatError> {
+        if !self.arch.matches(expected) {
+            return Err(FormatError::WrongArchitecture { expected: *expected, found: self.arch });
+        }
+        if weights.len() != size {
+            return Err(FormatError::WrongSize { expected: size, found: weights.len() });
+        }
+        Ok(())
+    }
+
+    pub fn check_hash(&self, weights: &[u8]) -> Result<(), FormatError> {
+        if hash(weights) == self.hash {
+            Ok(())
+        } else {
+            Err(FormatError::HashMismatch)
+        }
+    }
+}
+
+const fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+/// 64 bit FNV-1a
+fn hash(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+    bytes.iter().fold(OFFSET_BASIS, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(PRIME))
+}
+
+#[cfg(test)]
+mod header_tests {
+    use super::{Architecture, FormatError, NetHeader, HEADER_SIZE};
+
+    const SINGLE_BUCKET: Architecture = Architecture {
+        input_size: 768,
+        hidden_size: 16,
+        num_buckets: 2,
+        qa: 255,
+        qb: 64,
+        buckets: [1; 64],
+        output_buckets: 1,
+    };
+
+    fn net_file(arch: Architecture, weights: &[u8]) -> Vec<u8> {
+        let mut bytes = NetHeader::new(arch, weights).to_bytes().to_vec();
+        bytes.extend_from_slice(weights);
+        bytes
+    }
+
+    #[test]
+    fn output_buckets_round_trip() {
+        let weights = (0..=255).collect::<Vec<u8>>();
+        let eight_buckets = Architecture { output_buckets: 8, ..SINGLE_BUCKET };
+        let bytes = net_file(eight_buckets, &weights);
+
+        let (header, rest) = NetHeader::parse(&bytes).unwrap();
+        assert_eq!(header.arch, eight_buckets);
+        assert_eq!(header.to_bytes()[..], bytes[..HEADER_SIZE]);
+        assert_eq!(rest, weights);
+        assert_eq!(header.check_hash(rest), Ok(()));
+        assert_eq!(header.check_architecture(&eight_buckets, rest, weights.len()), Ok(()));
+        assert_eq!(
+            header.check_architecture(&SINGLE_BUCKET, rest, weights.len()),
+            Err(FormatError::WrongArchitecture { expected: SINGLE_BUCKET, found: eight_buckets })
+        );
+        let (header, _) = NetHeader::parse(&net_file(SINGLE_BUCKET, &weights)).unwrap();
+        assert!(header.check_architecture(&eight_buckets, rest, weights.len()).is_err());
+        assert!(FormatError::WrongArchitecture { expected: SINGLE_BUCKET, found: eight_buckets }
+            .to_string()
+            .contains("->1x8"));
+    }
+
+    #[test]
+    fn version_one_has_a_single_output_bucket() {
+        let weights = [0; 16];
+        let mut bytes = net_file(Architecture { output_buckets: 8, ..SINGLE_BUCKET }, &weights);
+        bytes[8..12].copy_from_slice(&1_u32.to_le_bytes());
+
+        let (header, _) = NetHeader::parse(&bytes).unwrap();
+        assert_eq!(header.version, 1);
+        assert_eq!(header.arch, SINGLE_BUCKET);
+        assert_eq!(header.check_architecture(&SINGLE_BUCKET, &weights, weights.len()), Ok(()));
+    }
+}
@@ -1,8 +1,17 @@
-use std::ops::{Deref, DerefMut};
+use std::{
+    fs,
+    ops::{Deref, DerefMut},
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
 
-use self::network::Network;
+use self::{
+    header::NetHeader,
+    network::{NetError, Network},
+};
 
 pub mod accumulator;
+pub mod header;
 pub mod network;
 mod simd;
 
@@ -11,7 +20,90 @@ type Block = [i16; HIDDEN_SIZE];
 pub const INPUT_SIZE: usize = 768;
 const HIDDEN_SIZE: usize = 1536;
 
-static NET: Network = unsafe { std::mem::transmute(*include_bytes!(env!("NETWORK"))) };
+/// Net compiled into the binary, used until another one is loaded. Its architecture is checked
+/// while compiling, and its hash by the build script. The default net predates headers, so the
+/// build script embeds a copy it gave a header instead.
+#[cfg(all(not(feature = "offline"), not(raw_net)))]
+static EMBEDDED: Network = {
+    use self::{header::FormatError, network::ARCHITECTURE};
+    use std::mem;
+
+    const BYTES: &[u8] = include_bytes!(env!("NETWORK"));
+    const SIZE: usize = mem::size_of::<Network>();
+    let weights = match NetHeader::parse(BYTES) {
+        Ok((header, weights)) => match header.check_architecture(&ARCHITECTURE, weights, SIZE) {
+            Ok(()) => weights,
+            Err(FormatError::WrongSize { .. }) => panic!("the embedded net isn't the size of this build's net"),
+            Err(_) => panic!("the embedded net was trained for a different architecture than this build"),
+        },
+        Err(_) => panic!("the embedded net has an invalid header"),
+    };
+    let Some(weights) = weights.first_chunk::<SIZE>() else { unreachable!() };
+    unsafe { mem::transmute(*weights) }
+};
+/// Nets without a header are only embedded as they are when built with `EVALFILE_RAW=1`. Only their
+/// size is checked, so nothing ensures they were trained for this build's architecture.
+#[cfg(all(not(feature = "offline"), raw_net))]
+static EMBEDDED: Network = unsafe { std::mem::transmute(*include_bytes!(env!("NETWORK"))) };
+#[cfg(feature = "offline")]
+static EMBEDDED: Network = network::material_net();
+/// Net loaded through the EvalFile option, or null while the embedded net is in use
+static LOADED: AtomicPtr<Network> = AtomicPtr::new(ptr::null_mut());
+
+/// Value of the EvalFile option that stands for the embedded net
+pub const EMBEDDED_NET: &str = "<embedded>";
+
+/// Shown whenever evaluations are made without a trained net
+pub const NO_NET_WARNING: &str =
+    "no trained net is loaded, so positions are evaluated by material alone. Load one with the EvalFile option.";
+
+/// Whether evaluations are made with a trained net rather than the hand-crafted one offline builds
+/// fall back on
+pub fn trained_net_loaded() -> bool {
+    !cfg!(feature = "offline") || !LOADED.load(Ordering::Relaxed).is_null()
+}
+
+/// The net evaluations are currently made with
+fn net() -> &'static Network {
+    let loaded = LOADED.load(Ordering::Acquire);
+    if loaded.is_null() {
+        &EMBEDDED
+    } else {
+        // Loaded nets are only freed by `load_net`, which never runs during a search
+        unsafe { &*loaded }
+    }
+}
+
+/// Evaluates with the net stored at `path` from now on, or the embedded net if `path` is
+/// `EMBEDDED_NET`. The previously loaded net is freed.
+///
+/// # Safety
+///
+/// No evaluation may run at the same time, as it could be reading from the net being freed. UCI
+/// options are only set between searches, which satisfies this.
+pub unsafe fn load_net(path: &str) -> Result<(), NetError> {
+    let net = if path == EMBEDDED_NET {
+        ptr::null_mut()
+    } else {
+        let bytes = fs::read(path).map_err(|e| NetError::Unreadable(format!("{path}: {e}")))?;
+        Box::into_raw(Network::from_bytes(&bytes)?)
+    };
+    let replaced = LOADED.swap(net, Ordering::AcqRel);
+    if !replaced.is_null() {
+        drop(unsafe { Box::from_raw(replaced) });
+    }
+    Ok(())
+}
+
+/// Writes the raw net at `input` to `output` with a header in front of it
+pub fn convert_net(input: &str, output: &str) -> Result<(), String> {
+    let raw = fs::read(input).map_err(|e| format!("couldn't read {input}: {e}"))?;
+    if NetHeader::parse(&raw).is_ok() {
+        return Err(format!("{input} already has a header"));
+    }
+    let bytes = Network::add_header(&raw).map_err(|e| e.to_string())?;
+    fs::write(output, bytes).map_err(|e| format!("couldn't write {output}: {e}"))
+}
 
 #[repr(C, align(64))]
 #[derive(Clone, Copy, Debug, PartialOrd, PartialEq)]
@@ -1,4 +1,12 @@
-use super::{Align64, Block, INPUT_SIZE};
+use std::{
+    alloc::{self, Layout},
+    fmt, mem, ptr,
+};
+
+use super::{
+    header::{Architecture, FormatError, NetHeader, HEADER_SIZE},
+    Align64, Block, HIDDEN_SIZE, INPUT_SIZE,
+};
 
 use crate::types::{
     pieces::{Color, Piece, NUM_PIECES},
@@ -18,6 +26,9 @@ pub(super) const RELU_MAX: i16 = QA as i16;
 pub(super) const SCALE: i32 = 400;
 
 pub const NUM_BUCKETS: usize = 9;
+/// Output buckets, picked by the number of pieces on the board. Nets trained without output buckets
+/// have a single one.
+pub const NUM_OUTPUT_BUCKETS: usize = 1;
 
 #[rustfmt::skip]
 pub static BUCKETS: [usize; 64] = [
@@ -31,16 +42,125 @@ pub static BUCKETS: [usize; 64] = [
     8, 8, 8, 8, 17, 17, 17, 17,
 ];
 
+/// Architecture of the nets this build evaluates with
+pub(super) const ARCHITECTURE: Architecture = Architecture {
+    input_size: INPUT_SIZE as u32,
+    hidden_size: HIDDEN_SIZE as u32,
+    num_buckets: NUM_BUCKETS as u32,
+    qa: QA,
+    qb: QB,
+    output_buckets: NUM_OUTPUT_BUCKETS as u32,
+    buckets: {
+        let mut buckets = [0; 64];
+        let mut sq = 0;
+        while sq < buckets.len() {
+            buckets[sq] = BUCKETS[sq] as u8;
+            sq += 1;
+        }
+        buckets
+    },
+};
+
 #[derive(Debug)]
 #[repr(C, align(64))]
 pub(super) struct Network {
     pub feature_weights: [Align64<Block>; INPUT_SIZE * NUM_BUCKETS],
     pub feature_bias: Align64<Block>,
-    pub output_weights: [Align64<Block>; 2],
-    pub output_bias: i16,
+    /// Weights applied to the side to move's accumulator, then the other side's, for each output
+    /// bucket
+    pub output_weights: [[Align64<Block>; 2]; NUM_OUTPUT_BUCKETS],
+    pub output_bias: [i16; NUM_OUTPUT_BUCKETS],
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NetError {
+    Unreadable(String),
+    /// Boxed since format errors describe whole architectures
+    Invalid(Box<FormatError>),
+}
+
+impl From<FormatError> for NetError {
+    fn from(e: FormatError) -> Self {
+        Self::Invalid(Box::new(e))
+    }
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unreadable(e) => write!(f, "couldn't read {e}"),
+            Self::Invalid(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Stands in for a trained net in offline builds. Every input feature activates a hidden neuron of
+/// its own, fully, so the output weights act as a table of piece values.
+#[cfg(feature = "offline")]
+pub(super) const fn material_net() -> Network {
+    /// Centipawn values in the order of `PieceName`
+    const VALUES: [i32; NUM_PIECES] = [100, 300, 300, 500, 900, 0];
+    const { assert!(INPUT_SIZE <= HIDDEN_SIZE) };
+
+    let mut net: Network = unsafe { mem::zeroed() };
+    let mut feature = 0;
+    while feature < INPUT_SIZE {
+        let mut bucket = 0;
+        while bucket < NUM_BUCKETS {
+            net.feature_weights[bucket * INPUT_SIZE + feature].0[feature] = RELU_MAX;
+            bucket += 1;
+        }
+
+        // Features of the side whose view the accumulator holds come first, and each piece
+        // contributes to both views, so each view counts for half of its value
+        let value = VALUES[feature % (NUM_PIECES * NUM_SQUARES) / NUM_SQUARES];
+        let value = if feature < NUM_PIECES * NUM_SQUARES { value } else { -value };
+        let weight = (value * QB / (2 * SCALE)) as i16;
+        let mut bucket = 0;
+        while bucket < NUM_OUTPUT_BUCKETS {
+            net.output_weights[bucket][0].0[feature] = weight;
+            net.output_weights[bucket][1].0[feature] = -weight;
+            bucket += 1;
+        }
+        feature += 1;
+    }
+    net
 }
 
 impl Network {
+    /// Checks the header of a net file, then copies the weights following it onto the heap,
+    /// keeping the alignment the SIMD code relies on
+    pub fn from_bytes(bytes: &[u8]) -> Result<Box<Self>, NetError> {
+        let (header, weights) = NetHeader::parse(bytes)?;
+        header.check_architecture(&ARCHITECTURE, weights, mem::size_of::<Self>())?;
+        header.check_hash(weights)?;
+
+        let layout = Layout::new::<Self>();
+        unsafe {
+            let net = alloc::alloc(layout);
+            if net.is_null() {
+                alloc::handle_alloc_error(layout);
+            }
+            // Every field is made of i16s, so any bytes make a valid net
+            ptr::copy_nonoverlapping(weights.as_ptr(), net, weights.len());
+            Ok(Box::from_raw(net.cast()))
+        }
+    }
+
+    /// Prepends a header to a net stored in the raw layout used before headers existed. Nothing
+    /// about the raw net can be checked besides its size, so it is trusted to have been trained for
+    /// this build's architecture.
+    pub fn add_header(raw: &[u8]) -> Result<Vec<u8>, NetError> {
+        let expected = mem::size_of::<Self>();
+        if raw.len() != expected {
+            return Err(FormatError::WrongSize { expected, found: raw.len() }.into());
+        }
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + raw.len());
+        bytes.extend_from_slice(&NetHeader::new(ARCHITECTURE, raw).to_bytes());
+        bytes.extend_from_slice(raw);
+        Ok(bytes)
+    }
+
     pub fn feature_idx(piece: Piece, mut sq: Square, mut king: Square, view: Color) -> usize {
         const COLOR_OFFSET: usize = NUM_SQUARES * NUM_PIECES;
         const PIECE_OFFSET: usize = NUM_SQUARES;
@@ -58,6 +178,10 @@ impl Network {
         BUCKETS[king] * INPUT_SIZE + pov * COLOR_OFFSET + usize::from(piece.name()) * PIECE_OFFSET + usize::from(sq)
     }
 
+    pub fn output_bucket(pieces: i32) -> usize {
+        piece_count_bucket(pieces, NUM_OUTPUT_BUCKETS)
+    }
+
     pub fn bucket(view: Color, mut sq: Square) -> usize {
         if view == Color::Black {
             sq = sq.flip_vertical();
@@ -66,6 +190,12 @@ impl Network {
     }
 }
 
+/// Splits the 31 possible piece counts, from the two kings alone to all 32 pieces, evenly between
+/// `buckets` output buckets. When `buckets` divides 32 this is the usual `(pieces - 2) / (32 / buckets)`.
+const fn piece_count_bucket(pieces: i32, buckets: usize) -> usize {
+    (pieces as usize).saturating_sub(2) * buckets / 31
+}
+
 #[cfg(all(not(target_feature = "avx2"), not(feature = "avx512")))]
 fn screlu(i: i16) -> i32 {
     crelu(i) * crelu(i)
@@ -99,9 +229,83 @@ pub(super) fn flatten(acc: &Block, weights: &Block) -> i32 {
 
 #[cfg(test)]
 mod nnue_tests {
-    use std::{hint::black_box, time::Instant};
+    use std::{hint::black_box, mem, slice, time::Instant};
 
-    use crate::{board::Board, fen::STARTING_FEN};
+    use super::{piece_count_bucket, Network, ARCHITECTURE, NUM_OUTPUT_BUCKETS};
+    use crate::{
+        board::Board,
+        eval::{
+            header::{FormatError, HEADER_SIZE},
+            EMBEDDED,
+        },
+        fen::STARTING_FEN,
+    };
+
+    #[test]
+    fn load_from_bytes() {
+        let raw = unsafe { slice::from_raw_parts((&raw const EMBEDDED).cast::<u8>(), mem::size_of::<Network>()) };
+        let bytes = Network::add_header(raw).unwrap();
+        let net = Network::from_bytes(&bytes).unwrap();
+        assert_eq!((&raw const *net) as usize % 64, 0);
+        assert_eq!(net.feature_bias, EMBEDDED.feature_bias);
+        assert_eq!(net.output_weights, EMBEDDED.output_weights);
+        assert_eq!(net.output_bias, EMBEDDED.output_bias);
+
+        let load = |edit: fn(&mut Vec<u8>)| {
+            let mut bytes = bytes.clone();
+            edit(&mut bytes);
+            Network::from_bytes(&bytes).map(|_| ()).unwrap_err()
+        };
+        assert_eq!(load(|bytes| bytes.drain(..HEADER_SIZE).for_each(drop)), FormatError::MissingHeader.into());
+        assert_eq!(load(|bytes| bytes[8] = 3), FormatError::UnsupportedVersion(3).into());
+        let mut found = ARCHITECTURE;
+        found.hidden_size = 1024;
+        assert_eq!(
+            load(|bytes| bytes[16..20].copy_from_slice(&1024_u32.to_le_bytes())),
+            FormatError::WrongArchitecture { expected: ARCHITECTURE, found }.into()
+        );
+        assert!(load(|bytes| bytes[64 + 63] = 0).to_string().contains("bucket layout"));
+        let expected = mem::size_of::<Network>();
+        assert_eq!(
+            load(|bytes| bytes.truncate(bytes.len() - 64)),
+            FormatError::WrongSize { expected, found: expected - 64 }.into()
+        );
+        assert_eq!(load(|bytes| bytes[HEADER_SIZE + 1000] ^= 1), FormatError::HashMismatch.into());
+        assert!(Network::add_header(&bytes).is_err());
+    }
+
+    #[test]
+    fn output_buckets() {
+        assert!((2..=32).all(|pieces| Network::output_bucket(pieces) < NUM_OUTPUT_BUCKETS));
+
+        for num_buckets in 1..=31 {
+            let buckets = (2..=32).map(|pieces| piece_count_bucket(pieces, num_buckets)).collect::<Vec<_>>();
+            // Every bucket gets used, by a contiguous range of piece counts
+            assert_eq!(buckets[0], 0);
+            assert_eq!(buckets[30], num_buckets - 1);
+            assert!(buckets.windows(2).all(|w| w[1] == w[0] || w[1] == w[0] + 1));
+        }
+        for num_buckets in [1, 2, 4, 8, 16] {
+            assert!((2..=32)
+                .all(|pieces| piece_count_bucket(pieces, num_buckets) == (pieces - 2) as usize / (32 / num_buckets)));
+        }
+
+        let eight = [2, 5, 6, 17, 18, 29, 30, 32].map(|pieces| piece_count_bucket(pieces, 8));
+        assert_eq!(eight, [0, 0, 1, 3, 4, 6, 7, 7]);
+    }
+
+    #[cfg(feature = "offline")]
+    #[test]
+    fn material_net_buckets() {
+        assert!(EMBEDDED.output_weights.iter().all(|weights| *weights == EMBEDDED.output_weights[0]));
+        assert!(EMBEDDED.output_bias.iter().all(|&bias| bias == 0));
+
+        // A queen is worth the same whichever output bucket the piece count selects
+        for fen in ["3qk3/8/8/8/8/8/8/4K3 w - - 0 1", "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNB1KBNR w KQkq - 0 1"] {
+            let board = Board::from_fen(fen);
+            assert!((-905..=-895).contains(&board.new_accumulator().raw_evaluate(&board)), "{fen}");
+        }
+    }
 
     #[test]
     fn inference_benchmark() {
@@ -116,4 +320,50 @@ mod nnue_tests {
         println!("{} ns per iter", duration / iters);
         dbg!(duration / iters);
     }
+
+    #[cfg(all(not(feature = "avx512"), target_feature = "avx2"))]
+    #[test]
+    fn update_benchmark() {
+        use crate::{
+            eval::{accumulator::scalar_update, simd::avx2},
+            types::pieces::Color,
+        };
+
+        let board = Board::from_fen("r3k2r/2pb1ppp/2pp1q2/p7/1nP1B3/1P2P3/P2N1PPP/R2QK2R w KQkq a6 0 14");
+        let old = board.new_accumulator();
+        let king = board.king_square(Color::White);
+        let pieces = board.occupancies().map(|sq| Network::feature_idx(board.piece_at(sq), sq, king, Color::White));
+        let features = pieces.collect::<Vec<_>>();
+        let iters = 1_000_000_u128;
+
+        let mut acc = old;
+        let start = Instant::now();
+        for i in 0..iters as usize {
+            let (a, s) = (features[i % features.len()], features[(i + 1) % features.len()]);
+            acc.scalar_add_sub(black_box(&old), a, s, Color::White);
+            black_box(&acc);
+        }
+        let scalar = start.elapsed().as_nanos() / iters;
+        let start = Instant::now();
+        for i in 0..iters as usize {
+            let (a, s) = (features[i % features.len()], features[(i + 1) % features.len()]);
+            unsafe { acc.avx2_add_sub(black_box(&old), a, s, Color::White) };
+            black_box(&acc);
+        }
+        let simd = start.elapsed().as_nanos() / iters;
+        println!("add_sub: {scalar} ns scalar, {simd} ns avx2");
+
+        let iters = 100_000_u128;
+        let start = Instant::now();
+        for _ in 0..iters {
+            scalar_update(black_box(&mut acc.vals[Color::White]), &features, &[]);
+        }
+        let scalar = start.elapsed().as_nanos() / iters;
+        let start = Instant::now();
+        for _ in 0..iters {
+            unsafe { avx2::update(black_box(&mut acc.vals[Color::White]), &features, &[]) };
+        }
+        let simd = start.elapsed().as_nanos() / iters;
+        println!("refresh: {scalar} ns scalar, {simd} ns avx2");
+    }
 }
@@ -2,12 +2,19 @@
 pub(crate) mod avx2 {
     use std::arch::x86_64::*;
 
+    use crate::eval::accumulator::Accumulator;
     use crate::eval::network::{RELU_MAX, RELU_MIN};
-    use crate::eval::{Block, HIDDEN_SIZE};
+    use crate::eval::{net, Align64, Block, HIDDEN_SIZE};
+    use crate::types::pieces::Color;
 
     const CHUNK_SIZE: usize = 16;
     /// Number of SIMD vectors contained within one hidden layer
     const REQUIRED_ITERS: usize = HIDDEN_SIZE / CHUNK_SIZE;
+    /// Vectors of the accumulator kept in registers at once while refreshing it. AVX2 has 16
+    /// registers, leaving room for the weights being added.
+    const UPDATE_REGISTERS: usize = 8;
+    const UPDATE_TILE: usize = UPDATE_REGISTERS * CHUNK_SIZE;
+    const _: () = assert!(HIDDEN_SIZE.is_multiple_of(UPDATE_TILE));
 
     #[inline]
     pub unsafe fn flatten(acc: &Block, weights: &Block) -> i32 {
@@ -47,6 +54,102 @@ pub(crate) mod avx2 {
 
         _mm256_min_epi16(_mm256_max_epi16(i, min), max)
     }
+
+    /// Adds and subtracts features from an accumulator a tile of registers at a time, so each part
+    /// of the accumulator is loaded and stored once however many features change
+    pub unsafe fn update(acc: &mut Align64<Block>, adds: &[usize], subs: &[usize]) {
+        let weights = &net().feature_weights;
+        let mut regs = [_mm256_setzero_si256(); UPDATE_REGISTERS];
+
+        for tile in 0..HIDDEN_SIZE / UPDATE_TILE {
+            let offset = tile * UPDATE_TILE;
+
+            for (i, reg) in regs.iter_mut().enumerate() {
+                *reg = _mm256_load_si256(acc.as_ptr().add(offset + i * CHUNK_SIZE).cast());
+            }
+
+            for &add in adds {
+                let weights = weights[add].as_ptr().add(offset);
+                for (i, reg) in regs.iter_mut().enumerate() {
+                    *reg = _mm256_add_epi16(*reg, _mm256_load_si256(weights.add(i * CHUNK_SIZE).cast()));
+                }
+            }
+
+            for &sub in subs {
+                let weights = weights[sub].as_ptr().add(offset);
+                for (i, reg) in regs.iter_mut().enumerate() {
+                    *reg = _mm256_sub_epi16(*reg, _mm256_load_si256(weights.add(i * CHUNK_SIZE).cast()));
+                }
+            }
+
+            for (i, reg) in regs.iter().enumerate() {
+                _mm256_store_si256(acc.as_mut_ptr().add(offset + i * CHUNK_SIZE).cast(), *reg);
+            }
+        }
+    }
+
+    impl Accumulator {
+        pub(crate) unsafe fn avx2_add_sub(&mut self, old: &Accumulator, a1: usize, s1: usize, side: Color) {
+            let weights = &net().feature_weights;
+            for i in 0..REQUIRED_ITERS {
+                let w_acc = _mm256_load_si256(old[side].as_ptr().add(i * CHUNK_SIZE).cast());
+                let w_add = _mm256_load_si256(weights[a1].as_ptr().add(i * CHUNK_SIZE).cast());
+                let w_sub = _mm256_load_si256(weights[s1].as_ptr().add(i * CHUNK_SIZE).cast());
+
+                let w_updated = _mm256_add_epi16(w_acc, w_add);
+                let w_updated = _mm256_sub_epi16(w_updated, w_sub);
+                _mm256_store_si256(self[side].as_mut_ptr().add(i * CHUNK_SIZE).cast(), w_updated);
+            }
+        }
+
+        pub(crate) unsafe fn avx2_add_sub_sub(
+            &mut self,
+            old: &Accumulator,
+            a1: usize,
+            s1: usize,
+            s2: usize,
+            side: Color,
+        ) {
+            let weights = &net().feature_weights;
+            for i in 0..REQUIRED_ITERS {
+                let w_acc = _mm256_load_si256(old[side].as_ptr().add(i * CHUNK_SIZE).cast());
+                let w_add = _mm256_load_si256(weights[a1].as_ptr().add(i * CHUNK_SIZE).cast());
+                let w_sub1 = _mm256_load_si256(weights[s1].as_ptr().add(i * CHUNK_SIZE).cast());
+                let w_sub2 = _mm256_load_si256(weights[s2].as_ptr().add(i * CHUNK_SIZE).cast());
+
+                let w_updated = _mm256_add_epi16(w_acc, w_add);
+                let w_updated = _mm256_sub_epi16(w_updated, w_sub1);
+                let w_updated = _mm256_sub_epi16(w_updated, w_sub2);
+                _mm256_store_si256(self[side].as_mut_ptr().add(i * CHUNK_SIZE).cast(), w_updated);
+            }
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub(crate) unsafe fn avx2_add_add_sub_sub(
+            &mut self,
+            old: &Accumulator,
+            a1: usize,
+            a2: usize,
+            s1: usize,
+            s2: usize,
+            side: Color,
+        ) {
+            let weights = &net().feature_weights;
+            for i in 0..REQUIRED_ITERS {
+                let w_acc = _mm256_load_si256(old[side].as_ptr().add(i * CHUNK_SIZE).cast());
+                let w_add1 = _mm256_load_si256(weights[a1].as_ptr().add(i * CHUNK_SIZE).cast());
+                let w_add2 = _mm256_load_si256(weights[a2].as_ptr().add(i * CHUNK_SIZE).cast());
+                let w_sub1 = _mm256_load_si256(weights[s1].as_ptr().add(i * CHUNK_SIZE).cast());
+                let w_sub2 = _mm256_load_si256(weights[s2].as_ptr().add(i * CHUNK_SIZE).cast());
+
+                let w_updated = _mm256_add_epi16(w_acc, w_add1);
+                let w_updated = _mm256_add_epi16(w_updated, w_add2);
+                let w_updated = _mm256_sub_epi16(w_updated, w_sub1);
+                let w_updated = _mm256_sub_epi16(w_updated, w_sub2);
+                _mm256_store_si256(self[side].as_mut_ptr().add(i * CHUNK_SIZE).cast(), w_updated);
+            }
+        }
+    }
 }
 
 #[cfg(feature = "avx512")]
@@ -56,7 +159,7 @@ pub(crate) mod avx512 {
 
     use crate::eval::accumulator::Accumulator;
     use crate::eval::network::{RELU_MAX, RELU_MIN};
-    use crate::eval::{Block, HIDDEN_SIZE, NET};
+    use crate::eval::{net, Block, HIDDEN_SIZE};
     use crate::types::pieces::Color;
 
     const CHUNK_SIZE: usize = 32;
@@ -95,7 +198,7 @@ pub(crate) mod avx512 {
         }
 
         pub(crate) unsafe fn avx512_add_sub(&mut self, old: &Accumulator, a1: usize, s1: usize, side: Color) {
-            let weights = &NET.feature_weights;
+            let weights = &net().feature_weights;
             for i in 0..REQUIRED_ITERS {
                 let w_acc = _mm512_load_si512(old[side].as_ptr().add(i * CHUNK_SIZE).cast());
                 let w_add = _mm512_load_si512(weights[a1].as_ptr().add(i * CHUNK_SIZE).cast());
@@ -115,7 +218,7 @@ pub(crate) mod avx512 {
             s2: usize,
             side: Color,
         ) {
-            let weights = &NET.feature_weights;
+            let weights = &net().feature_weights;
             for i in 0..REQUIRED_ITERS {
                 let w_acc = _mm512_load_si512(old[side].as_ptr().add(i * CHUNK_SIZE).cast());
                 let w_add = _mm512_load_si512(weights[a1].as_ptr().add(i * CHUNK_SIZE).cast());
@@ -139,7 +242,7 @@ pub(crate) mod avx512 {
             s2: usize,
             side: Color,
         ) {
-            let weights = &NET.feature_weights;
+            let weights = &net().feature_weights;
             for i in 0..REQUIRED_ITERS {
                 let w_acc = _mm512_load_si512(old[side].as_ptr().add(i * CHUNK_SIZE).cast());
                 let w_add1 = _mm512_load_si512(weights[a1].as_ptr().add(i * CHUNK_SIZE).cast());
@@ -156,3 +259,47 @@ pub(crate) mod avx512 {
         }
     }
 }
+
+#[cfg(all(test, not(feature = "avx512"), target_feature = "avx2"))]
+mod simd_tests {
+    use super::avx2;
+    use crate::{
+        board::Board,
+        eval::{accumulator::scalar_update, network::NUM_BUCKETS, INPUT_SIZE},
+        magics::Rng,
+        types::pieces::Color,
+    };
+
+    const FEATURES: u64 = (INPUT_SIZE * NUM_BUCKETS) as u64;
+
+    #[test]
+    fn matches_scalar() {
+        let old =
+            Board::from_fen("r3k2r/2pb1ppp/2pp1q2/p7/1nP1B3/1P2P3/P2N1PPP/R2QK2R w KQkq a6 0 14").new_accumulator();
+        let mut rng = Rng::with_seed(11);
+        let mut feature = || (rng.next_u64() % FEATURES) as usize;
+
+        for side in [Color::White, Color::Black].into_iter().cycle().take(200) {
+            let (a1, a2, s1, s2) = (feature(), feature(), feature(), feature());
+            let (mut simd, mut scalar) = (old, old);
+
+            unsafe { simd.avx2_add_sub(&old, a1, s1, side) };
+            scalar.scalar_add_sub(&old, a1, s1, side);
+            assert_eq!(simd.vals, scalar.vals);
+
+            unsafe { simd.avx2_add_sub_sub(&old, a1, s1, s2, side) };
+            scalar.scalar_add_sub_sub(&old, a1, s1, s2, side);
+            assert_eq!(simd.vals, scalar.vals);
+
+            unsafe { simd.avx2_add_add_sub_sub(&old, a1, a2, s1, s2, side) };
+            scalar.scalar_add_add_sub_sub(&old, a1, a2, s1, s2, side);
+            assert_eq!(simd.vals, scalar.vals);
+
+            let adds = (0..feature() % 32).map(|_| feature()).collect::<Vec<_>>();
+            let subs = (0..feature() % 32).map(|_| feature()).collect::<Vec<_>>();
+            unsafe { avx2::update(&mut simd.vals[side], &adds, &subs) };
+            scalar_update(&mut scalar.vals[side], &adds, &subs);
+            assert_eq!(simd.vals, scalar.vals);
+        }
+    }
+}
@@ -1,7 +1,13 @@
+use std::fmt;
+
 use crate::{
-    chess_move::Castle,
+    attack_boards::RANKS,
+    chess_move::{
+        Castle,
+        Direction::{North, South},
+    },
     types::{
-        pieces::{Color, Piece},
+        pieces::{Color, Piece, PieceName},
         square::{Square, SQUARE_NAMES},
     },
 };
@@ -11,79 +17,239 @@ use super::board::Board;
 /// Fen string for the starting position of a board
 pub const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
 
-/// Takes in a string in fen notation and returns a board state
+/// Reasons a fen string can be rejected
+#[derive(Debug, PartialEq, Eq)]
+pub enum FenError {
+    MissingField(&'static str),
+    TooManyFields,
+    /// The placement field doesn't have exactly eight ranks
+    RankCount(usize),
+    /// A rank doesn't describe exactly eight squares
+    RankLength(usize),
+    InvalidPiece(char),
+    InvalidSideToMove(String),
+    InvalidCastling(String),
+    /// Castling rights were given without the king and rook on their starting squares
+    CastlingWithoutPieces(char),
+    InvalidEnPassant(String),
+    KingCount {
+        color: Color,
+        count: i32,
+    },
+    /// A side has more pieces than the 16 it starts with
+    TooManyPieces {
+        color: Color,
+        count: i32,
+    },
+    TooManyPawns {
+        color: Color,
+        count: i32,
+    },
+    PawnOnBackRank(Square),
+    /// The side that just moved left its king in check
+    OpponentInCheck,
+    InvalidMoveCounter(String),
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingField(field) => write!(f, "missing {field}"),
+            Self::TooManyFields => write!(f, "too many fields"),
+            Self::RankCount(count) => write!(f, "expected 8 ranks, found {count}"),
+            Self::RankLength(rank) => write!(f, "rank {rank} does not have 8 squares"),
+            Self::InvalidPiece(c) => write!(f, "unrecognized piece '{c}'"),
+            Self::InvalidSideToMove(stm) => write!(f, "invalid side to move '{stm}'"),
+            Self::InvalidCastling(rights) => write!(f, "invalid castling rights '{rights}'"),
+            Self::CastlingWithoutPieces(c) => write!(f, "castling right '{c}' without king and rook in place"),
+            Self::InvalidEnPassant(sq) => write!(f, "impossible en passant square '{sq}'"),
+            Self::KingCount { color, count } => write!(f, "{color:?} has {count} kings"),
+            Self::TooManyPieces { color, count } => write!(f, "{color:?} has {count} pieces"),
+            Self::TooManyPawns { color, count } => write!(f, "{color:?} has {count} pawns"),
+            Self::PawnOnBackRank(sq) => write!(f, "pawn on back rank at {sq:?}"),
+            Self::OpponentInCheck => write!(f, "side not to move is in check"),
+            Self::InvalidMoveCounter(counter) => write!(f, "invalid move counter '{counter}'"),
+        }
+    }
+}
+
 impl Board {
+    /// Takes in a string in fen notation and returns a board state. Panics if the fen is invalid.
     pub fn from_fen(fen_string: &str) -> Self {
+        Self::try_from_fen(fen_string).unwrap_or_else(|e| panic!("Invalid fen {fen_string}: {e}"))
+    }
+
+    /// Takes in a string in fen notation and returns a board state, provided the fen describes a
+    /// position that could arise in a game. The castling, en passant and move counter fields may be
+    /// left out.
+    pub fn try_from_fen(fen_string: &str) -> Result<Self, FenError> {
         let mut board = Self::empty();
-        let mut row = 7;
-        let pieces = fen_string.split(['/', ' ']).collect::<Vec<_>>();
-        // FEN strings have 13 entries (if each slash and each space delimit an entry)
-        let mut iter = pieces.iter();
-        let mut start = 7;
-        let end = 0;
-        let step: i32 = -1;
-        while start >= end {
-            // Loop handles reading board part of fen string
-            let entry = iter.next().unwrap();
-            let mut idx = 0;
-            for c in entry.chars() {
-                if c.is_ascii_digit() {
-                    idx += c.to_digit(10).unwrap();
+        let mut fields = fen_string.split_whitespace();
+
+        let placement = fields.next().ok_or(FenError::MissingField("piece placement"))?;
+        let ranks = placement.split('/').collect::<Vec<_>>();
+        if ranks.len() != 8 {
+            return Err(FenError::RankCount(ranks.len()));
+        }
+        for (row, rank) in ranks.iter().rev().enumerate() {
+            let mut file = 0;
+            for c in rank.chars() {
+                if let Some(gap) = c.to_digit(10).filter(|gap| (1..=8).contains(gap)) {
+                    file += gap;
                     continue;
                 }
-                let square = row * 8 + idx;
-                let square = Square(square);
                 const PIECES: &str = "PpNnBbRrQqKk";
-                let Some(i) = PIECES.chars().position(|x| x == c) else {
-                    panic!("Unrecognized char {c}, board could not be made");
-                };
-                board.place_piece(Piece::from(i), square);
-                idx += 1;
+                let i = PIECES.chars().position(|x| x == c).ok_or(FenError::InvalidPiece(c))?;
+                if file >= 8 {
+                    return Err(FenError::RankLength(row + 1));
+                }
+                board.place_piece(Piece::from(i), Square(row as u32 * 8 + file));
+                file += 1;
+            }
+            if file != 8 {
+                return Err(FenError::RankLength(row + 1));
+            }
+        }
+
+        board.stm = match fields.next().ok_or(FenError::MissingField("side to move"))? {
+            "w" => Color::White,
+            "b" => Color::Black,
+            stm => return Err(FenError::InvalidSideToMove(stm.to_string())),
+        };
+
+        let castling = fields.next().unwrap_or("-");
+        if castling != "-" {
+            if !castling.chars().all(|c| "KQkqABCDEFGHabcdefgh".contains(c)) {
+                return Err(FenError::InvalidCastling(castling.to_string()));
+            }
+            board.parse_castling(castling)?;
+        }
+
+        let en_passant = fields.next().unwrap_or("-");
+        if en_passant != "-" {
+            let invalid = || FenError::InvalidEnPassant(en_passant.to_string());
+            let chars = en_passant.chars().collect::<Vec<_>>();
+            if chars.len() != 2 {
+                return Err(invalid());
             }
-            start += step;
-            row = row.saturating_sub(1);
+            board.en_passant_square = Some(Square(find_en_passant_square(&chars).ok_or_else(invalid)?));
         }
-        // 9th element: find who's turn it is to move
-        board.stm = match iter.next().unwrap().chars().next().unwrap() {
-            'w' => Color::White,
-            'b' => Color::Black,
-            _ => panic!("Invalid turn"),
+
+        let mut counter = |default| {
+            fields.next().map_or(Ok(default), |x| x.parse().map_err(|_| FenError::InvalidMoveCounter(x.to_string())))
         };
+        board.half_moves = counter(0)?;
+        // Full number of moves in the game: starts from 1 and incremented after black's first move
+        board.num_moves = counter(1)?;
+
+        if fields.next().is_some() {
+            return Err(FenError::TooManyFields);
+        }
+
+        board.validate()?;
         board.zobrist_hash = board.generate_hash();
         board.pawn_hash = board.pawn_hash();
         board.calculate_threats();
         board.pinned_and_checkers();
+        Ok(board)
+    }
 
-        // 10th bucket find who can still castle
-        // Order of array is white king castle, white queen castle, black king castle, black queen castle
-        let Some(next) = iter.next() else { return board };
-        board.castling_rights = parse_castling(next);
+    /// Reads castling rights in either X-FEN or Shredder-FEN. X-FEN uses KQkq for the outermost rook
+    /// on each side of the king, while Shredder-FEN names the file of the castling rook, which is
+    /// needed in Chess960 when two rooks share a side of the king.
+    fn parse_castling(&mut self, castling: &str) -> Result<(), FenError> {
+        for c in castling.chars() {
+            let color = if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+            let back_rank = match color {
+                Color::White => RANKS[0],
+                Color::Black => RANKS[7],
+            };
+            let king = self.piece_color(color, PieceName::King) & back_rank;
+            if king.count_bits() != 1 {
+                return Err(FenError::CastlingWithoutPieces(c));
+            }
+            let king = king.lsb();
+            let mut rooks = (self.piece_color(color, PieceName::Rook) & back_rank).into_iter();
+            let rook = match c.to_ascii_lowercase() {
+                'k' => rooks.filter(|sq| sq.file() > king.file()).last(),
+                'q' => rooks.find(|sq| sq.file() < king.file()),
+                file => rooks.find(|sq| sq.file() == file as u32 - 'a' as u32),
+            }
+            .ok_or(FenError::CastlingWithoutPieces(c))?;
+            let castle = match (color, rook.file() > king.file()) {
+                (Color::White, true) => Castle::WhiteKing,
+                (Color::White, false) => Castle::WhiteQueen,
+                (Color::Black, true) => Castle::BlackKing,
+                (Color::Black, false) => Castle::BlackQueen,
+            };
+            self.set_castling_right(castle, rook);
+        }
+        Ok(())
+    }
 
-        let Some(next) = iter.next() else { return board };
-        let en_passant_letters: Vec<char> = next.chars().collect();
-        let en_passant_idx = find_en_passant_square(&en_passant_letters);
-        if let Some(idx) = en_passant_idx {
-            board.en_passant_square = Some(Square(idx));
+    /// Checks that the pieces, castling rights and en passant square describe a reachable position
+    fn validate(&self) -> Result<(), FenError> {
+        for color in Color::iter() {
+            let count = self.piece_color(color, PieceName::King).count_bits();
+            if count != 1 {
+                return Err(FenError::KingCount { color, count });
+            }
+            let count = self.color(color).count_bits();
+            if count > 16 {
+                return Err(FenError::TooManyPieces { color, count });
+            }
+            let count = self.piece_color(color, PieceName::Pawn).count_bits();
+            if count > 8 {
+                return Err(FenError::TooManyPawns { color, count });
+            }
         }
-        board.zobrist_hash = board.generate_hash();
-        board.pawn_hash = board.pawn_hash();
 
-        let half_moves = iter.next();
-        if let Some(half_moves) = half_moves {
-            if let Ok(half_moves) = half_moves.parse() {
-                board.half_moves = half_moves;
+        if let Some(sq) = (self.piece(PieceName::Pawn) & (RANKS[0] | RANKS[7])).into_iter().next() {
+            return Err(FenError::PawnOnBackRank(sq));
+        }
+
+        if let Some(sq) = self.en_passant_square {
+            // The pawn that just moved two squares must sit in front of the en passant square, with
+            // both the en passant square and the square it came from empty
+            let (rank, pushed, origin) = match self.stm {
+                Color::White => (5, sq.checked_shift(South), sq.checked_shift(North)),
+                Color::Black => (2, sq.checked_shift(North), sq.checked_shift(South)),
+            };
+            let plausible = sq.rank() == rank
+                && self.piece_at(sq) == Piece::None
+                && origin.is_some_and(|origin| self.piece_at(origin) == Piece::None)
+                && pushed.is_some_and(|pushed| self.piece_at(pushed) == Piece::new(PieceName::Pawn, !self.stm));
+            if !plausible {
+                return Err(FenError::InvalidEnPassant(SQUARE_NAMES[sq].to_string()));
             }
         }
 
-        // Full number of moves in the game: starts from 1 and incremented after black's first move
-        let full_moves = iter.next();
-        if let Some(full_moves) = full_moves {
-            if let Ok(full_moves) = full_moves.parse() {
-                board.num_moves = full_moves;
+        if self.square_under_attack(self.stm, self.king_square(!self.stm)) {
+            return Err(FenError::OpponentInCheck);
+        }
+
+        Ok(())
+    }
+
+    /// Castles with the outermost rook on a side keep their X-FEN letter, others are written as the
+    /// file of the rook
+    fn castling_char(&self, castle: Castle, c: char) -> char {
+        let rook = self.castling_rook(castle);
+        let mut rooks = self.piece_color(castle.color(), PieceName::Rook) & rook.rank_bitboard();
+        let outermost = match castle {
+            Castle::WhiteKing | Castle::BlackKing => rooks.all(|sq| sq.file() <= rook.file()),
+            Castle::WhiteQueen | Castle::BlackQueen => rooks.all(|sq| sq.file() >= rook.file()),
+        };
+        if outermost {
+            c
+        } else {
+            let file = (b'a' + rook.file() as u8) as char;
+            if c.is_ascii_uppercase() {
+                file.to_ascii_uppercase()
+            } else {
+                file
             }
         }
-        assert_eq!(iter.next(), None);
-        board
     }
 
     pub fn to_fen(self) -> String {
@@ -124,17 +290,15 @@ impl Board {
         if self.castling_rights == 0 {
             str += "-";
         } else {
-            if self.can_castle(Castle::WhiteKing) {
-                str += "K";
-            }
-            if self.can_castle(Castle::WhiteQueen) {
-                str += "Q";
-            }
-            if self.can_castle(Castle::BlackKing) {
-                str += "k";
-            }
-            if self.can_castle(Castle::BlackQueen) {
-                str += "q";
+            for (castle, c) in [
+                (Castle::WhiteKing, 'K'),
+                (Castle::WhiteQueen, 'Q'),
+                (Castle::BlackKing, 'k'),
+                (Castle::BlackQueen, 'q'),
+            ] {
+                if self.can_castle(castle) {
+                    str.push(self.castling_char(castle, c));
+                }
             }
         }
 
@@ -155,46 +319,25 @@ impl Board {
     }
 }
 
-fn parse_castling(buf: &str) -> u32 {
-    let rights = buf.chars().fold(0, |x, ch| {
-        x | match ch {
-            'K' => Castle::WhiteKing as u32,
-            'Q' => Castle::WhiteQueen as u32,
-            'k' => Castle::BlackKing as u32,
-            'q' => Castle::BlackQueen as u32,
-            _ => 0,
-        }
-    });
-    rights
-}
-
 fn find_en_passant_square(vec: &[char]) -> Option<u32> {
-    if vec[0] == '-' {
+    let (&file, &rank) = (vec.first()?, vec.get(1)?);
+    if !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
         return None;
     }
     // Using base 20 allows program to convert letters directly to numbers instead of matching
     // against letters or some other workaround
-    let column = vec[0].to_digit(20).unwrap() - 10;
-    let row = (vec[1].to_digit(10).unwrap() - 1) * 8;
+    let column = file.to_digit(20)? - 10;
+    let row = (rank.to_digit(10)? - 1) * 8;
     Some(row + column)
 }
 
-pub fn parse_fen_from_buffer(buf: &[&str]) -> String {
-    let mut vec = buf.to_owned();
-    vec.remove(0);
-    vec.remove(0);
-    for _ in 6..vec.len() {
-        vec.pop();
-    }
-    vec.join(" ")
-}
-
 #[cfg(test)]
 mod fen_tests {
     use crate::{
         board::Board,
         chess_move::Castle,
-        fen::{find_en_passant_square, parse_castling},
+        fen::{find_en_passant_square, FenError},
+        types::{pieces::Color, square::Square},
     };
 
     #[test]
@@ -210,59 +353,67 @@ mod fen_tests {
         assert_eq!(Some(62), find_en_passant_square(&['g', '8']));
     }
 
+    fn castling(rights: &str) -> u32 {
+        Board::from_fen(&format!("r3k2r/8/8/8/8/8/8/R3K2R w {rights} - 0 1")).castling_rights
+    }
+
     #[test]
     fn test_parse_castling_white_king() {
-        let input = "K";
-        let result = parse_castling(input);
-        assert_eq!(result, Castle::WhiteKing as u32);
+        assert_eq!(castling("K"), Castle::WhiteKing as u32);
     }
 
     #[test]
     fn test_parse_castling_white_queen() {
-        let input = "Q";
-        let result = parse_castling(input);
-        assert_eq!(result, Castle::WhiteQueen as u32);
+        assert_eq!(castling("Q"), Castle::WhiteQueen as u32);
     }
 
     #[test]
     fn test_parse_castling_black_king() {
-        let input = "k";
-        let result = parse_castling(input);
-        assert_eq!(result, Castle::BlackKing as u32);
+        assert_eq!(castling("k"), Castle::BlackKing as u32);
     }
 
     #[test]
     fn test_parse_castling_black_queen() {
-        let input = "q";
-        let result = parse_castling(input);
-        assert_eq!(result, Castle::BlackQueen as u32);
+        assert_eq!(castling("q"), Castle::BlackQueen as u32);
     }
 
     #[test]
     fn test_parse_castling_invalid() {
-        let input = "X";
-        let result = parse_castling(input);
-        assert_eq!(result, 0); // Expecting 0 for invalid input
+        let result = Board::try_from_fen("r3k2r/8/8/8/8/8/8/R3K2R w X - 0 1");
+        assert_eq!(result.err(), Some(FenError::InvalidCastling("X".to_string())));
     }
 
     #[test]
     fn test_parse_multiple_castlings() {
-        let input = "KQkq";
-        let result = parse_castling(input);
-        // You need to define the expected result based on the combination of castling rights.
-        // For example, if all castling rights are allowed (KQkq), you can set the expected result to a specific value.
         let expected_result =
             Castle::WhiteKing as u32 | Castle::WhiteQueen as u32 | Castle::BlackKing as u32 | Castle::BlackQueen as u32;
-        assert_eq!(result, expected_result);
+        assert_eq!(castling("KQkq"), expected_result);
+        assert_eq!(castling("HAha"), expected_result);
     }
 
     #[test]
     fn test_parse_partial_castlings() {
-        let input = "Kk";
-        let result = parse_castling(input);
-        // Define the expected result for the combination of castling rights in the input.
         let expected_result = Castle::WhiteKing as u32 | Castle::BlackKing as u32;
-        assert_eq!(result, expected_result);
+        assert_eq!(castling("Kk"), expected_result);
+        assert_eq!(castling("Hh"), expected_result);
+    }
+
+    #[test]
+    fn chess960_castling() {
+        // Shredder-FEN names the rook's file, X-FEN only does so when the rook isn't the outermost
+        let board = Board::from_fen("1r2k1rr/8/8/8/8/8/8/RR2K2R w BHbg - 0 1");
+        assert_eq!(board.castling_rook(Castle::WhiteKing), Square::H1);
+        assert_eq!(board.castling_rook(Castle::WhiteQueen), Square::B1);
+        assert_eq!(board.castling_rook(Castle::BlackKing), Square::G8);
+        assert_eq!(board.castling_rook(Castle::BlackQueen), Square::B8);
+        assert_eq!(board.to_fen(), "1r2k1rr/8/8/8/8/8/8/RR2K2R w KBgq - 0 1");
+        assert_eq!(Board::from_fen(&board.to_fen()), board);
+
+        let board = Board::from_fen("rk2r3/8/8/8/8/8/8/RK2R3 w KQkq - 0 1");
+        assert_eq!(board.castling_rook(Castle::WhiteKing), Square::E1);
+        assert_eq!(board.castling_rook(Castle::WhiteQueen), Square::A1);
+        assert_eq!(board.castling_rook(Castle::BlackKing), Square::E8);
+        assert_eq!(Board::from_fen("rk2r3/8/8/8/8/8/8/RK2R3 w EAea - 0 1"), board);
     }
 
     #[test]
@@ -277,4 +428,61 @@ mod fen_tests {
             assert_eq!(fen, Board::from_fen(fen).to_fen());
         }
     }
+
+    #[test]
+    fn optional_fields() {
+        let board = Board::try_from_fen("4k3/8/8/8/8/8/8/4K3 b").unwrap();
+        assert_eq!(board.to_fen(), "4k3/8/8/8/8/8/8/4K3 b - - 0 1");
+    }
+
+    #[test]
+    fn malformed_fens() {
+        for (fen, err) in [
+            ("", FenError::MissingField("piece placement")),
+            ("4k3/8/8/8/8/8/8/4K3", FenError::MissingField("side to move")),
+            ("4k3/8/8/8/8/8/4K3 w - - 0 1", FenError::RankCount(7)),
+            ("4k3/8/8/8/8/8/8/4K3/8 w - - 0 1", FenError::RankCount(9)),
+            ("4k3/8/8/8/8/8/8/4K2 w - - 0 1", FenError::RankLength(1)),
+            ("4k3/8/8/8/8/8/8/4K4 w - - 0 1", FenError::RankLength(1)),
+            ("4k3/9/8/8/8/8/8/4K3 w - - 0 1", FenError::InvalidPiece('9')),
+            ("4k3/8/8/8/8/8/8/4X3 w - - 0 1", FenError::InvalidPiece('X')),
+            ("4k3/8/8/8/8/8/8/4K3 x - - 0 1", FenError::InvalidSideToMove("x".to_string())),
+            ("4k3/8/8/8/8/8/8/4K3 w KX - 0 1", FenError::InvalidCastling("KX".to_string())),
+            ("4k3/8/8/8/8/8/8/4K3 w - e9 0 1", FenError::InvalidEnPassant("e9".to_string())),
+            ("4k3/8/8/8/8/8/8/4K3 w - - x 1", FenError::InvalidMoveCounter("x".to_string())),
+            ("4k3/8/8/8/8/8/8/4K3 w - - 0 1 2", FenError::TooManyFields),
+        ] {
+            assert_eq!(Board::try_from_fen(fen).err(), Some(err), "{fen}");
+        }
+    }
+
+    #[test]
+    fn impossible_positions() {
+        for (fen, err) in [
+            ("8/8/8/8/8/8/8/4K3 w - - 0 1", FenError::KingCount { color: Color::Black, count: 0 }),
+            ("4k3/8/8/8/8/8/8/3KK3 w - - 0 1", FenError::KingCount { color: Color::White, count: 2 }),
+            (
+                "k7/pppppppp/pppppppp/pppppppp/PPPPPPPP/PPPPPPPP/PPPPPPPP/K7 w - - 0 1",
+                FenError::TooManyPieces { color: Color::White, count: 25 },
+            ),
+            ("4k3/pppppppp/p7/8/8/8/8/4K3 w - - 0 1", FenError::TooManyPawns { color: Color::Black, count: 9 }),
+            ("4k3/8/8/8/8/8/8/P3K3 w - - 0 1", FenError::PawnOnBackRank(Square::A1)),
+            ("4k2p/8/8/8/8/8/8/4K3 w - - 0 1", FenError::PawnOnBackRank(Square::H8)),
+            ("4k3/8/8/8/8/8/8/4K3 w K - 0 1", FenError::CastlingWithoutPieces('K')),
+            ("4k2r/8/8/8/8/8/8/4K3 w q - 0 1", FenError::CastlingWithoutPieces('q')),
+            ("4k3/8/8/8/8/8/8/R3K2R w KQk - 0 1", FenError::CastlingWithoutPieces('k')),
+            ("4k3/8/8/8/8/8/8/R3K2R w G - 0 1", FenError::CastlingWithoutPieces('G')),
+            ("4k3/8/8/8/8/8/4K3/R6R w K - 0 1", FenError::CastlingWithoutPieces('K')),
+            // No pawn could have just made a double push
+            ("4k3/8/8/8/8/8/8/4K3 w - e6 0 1", FenError::InvalidEnPassant("e6".to_string())),
+            // Wrong rank for the side to move
+            ("4k3/8/8/4p3/8/8/8/4K3 b - e6 0 1", FenError::InvalidEnPassant("e6".to_string())),
+            ("4k3/8/8/8/8/8/8/r3K3 b - - 0 1", FenError::OpponentInCheck),
+            ("4k2R/8/8/8/8/8/8/4K3 w - - 0 1", FenError::OpponentInCheck),
+        ] {
+            assert_eq!(Board::try_from_fen(fen).err(), Some(err), "{fen}");
+        }
+
+        assert!(Board::try_from_fen("4k3/8/8/4p3/8/8/8/4K3 w - e6 0 1").is_ok());
+    }
 }
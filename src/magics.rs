@@ -31,6 +31,11 @@ impl Default for Rng {
 }
 
 impl Rng {
+    /// Generator starting from a particular seed. Xorshift never leaves zero, so it isn't allowed.
+    pub fn with_seed(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
     pub fn next_u64(&mut self) -> u64 {
         self.0 ^= self.0 << 13;
         self.0 ^= self.0 >> 7;
@@ -104,7 +109,7 @@ pub fn queen_attacks(sq: Square, occupied: Bitboard) -> Bitboard {
     bishop_attacks(sq, occupied) | rook_attacks(sq, occupied)
 }
 
-/// <https://analog-hors.github.io/site/magic-bitboards/>
+// <https://analog-hors.github.io/site/magic-bitboards/>
 // impl Magics {
 //     pub fn bishop_attacks(&self, occupied: Bitboard, sq: Square) -> Bitboard {
 //         let magic = &self.bishop_magics[sq];
@@ -166,7 +171,7 @@ pub fn queen_attacks(sq: Square, occupied: Bitboard) -> Bitboard {
 //     a
 // }
 
-/// Extracts move bitboards using known constants
+// Extracts move bitboards using known constants
 // fn create_table(sq: Square, deltas: [Direction; 4], magics: &[Magi]) -> Vec<Bitboard> {
 //     let magic_entry =
 //         if deltas[0] == North {  } else { BISHOP_MAGICS[sq] };
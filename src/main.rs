@@ -2,11 +2,14 @@
 #![deny(unused_must_use)]
 #![cfg_attr(feature = "avx512", feature(stdarch_x86_avx512))]
 
+mod analyze;
 mod attack_boards;
 mod bench;
 mod board;
 mod chess_move;
 mod correction;
+mod datagen;
+mod epd;
 mod eval;
 mod fen;
 mod history_table;
@@ -15,22 +18,78 @@ mod movegen;
 mod movelist;
 mod movepicker;
 mod perft;
+mod pgn;
+mod san;
 mod search;
 mod see;
 mod thread;
+mod tournament;
 mod transposition;
 mod types;
 mod uci;
 mod zobrist;
 
-use crate::bench::bench;
-use std::env;
+use crate::bench::standalone_bench;
+use search::SearchLimits;
+use std::{env, process::exit, time::Duration};
 use uci::main_loop;
 
 fn main() {
-    if env::args().any(|x| x == *"bench") {
-        bench();
-    } else {
-        main_loop();
+    let args = env::args().collect::<Vec<_>>();
+    if !eval::trained_net_loaded() {
+        eprintln!("warning: {}", eval::NO_NET_WARNING);
     }
+    let usage = |usage: &str| Err(format!("usage: {} {usage}", args[0]));
+    let result = match args.get(1).map(String::as_str) {
+        Some("bench") => {
+            let bench_args = args[2..].iter().map(String::as_str).collect::<Vec<_>>();
+            uci::parse_bench(&bench_args).and_then(|config| standalone_bench(&config)).map_err(|e| e.to_string())
+        }
+        Some("convert") => match args.get(2) {
+            Some(path) => pgn::convert(path),
+            None => usage("convert <pgn>"),
+        },
+        Some("convertnet") => match (args.get(2), args.get(3)) {
+            (Some(input), Some(output)) => eval::convert_net(input, output),
+            _ => usage("convertnet <raw net> <output>"),
+        },
+        Some("analyze") => match (args.get(2), analyze_nodes(args.get(3..).unwrap_or_default())) {
+            (Some(path), Some(nodes)) => analyze::analyze(path, nodes),
+            _ => usage("analyze <pgn> [--nodes N]"),
+        },
+        Some("epd") => match (args.get(2), epd_limits(args.get(3..).unwrap_or_default())) {
+            (Some(path), Some(limits)) => epd::run_suite(path, limits),
+            _ => usage("epd <file> [--time <ms>] [--nodes N]"),
+        },
+        Some("datagen") => datagen::run(&args[2..]),
+        Some("match") => tournament::run(&args[2..]),
+        _ => main_loop(),
+    };
+    if let Err(e) = result {
+        eprintln!("{e}");
+        exit(1);
+    }
+}
+
+/// Nodes to search per position given the arguments following the PGN path of `analyze`
+fn analyze_nodes(args: &[String]) -> Option<u64> {
+    match args {
+        [] => Some(analyze::DEFAULT_NODES),
+        [flag, nodes] if flag == "--nodes" => nodes.parse().ok(),
+        _ => None,
+    }
+}
+
+/// Limits for each position of an EPD suite. At least one is needed, since the search would
+/// otherwise never end.
+fn epd_limits(args: &[String]) -> Option<SearchLimits> {
+    let mut limits = SearchLimits::default();
+    for pair in args.chunks(2) {
+        match pair {
+            [flag, ms] if flag == "--time" => limits.movetime = Some(Duration::from_millis(ms.parse().ok()?)),
+            [flag, nodes] if flag == "--nodes" => limits.nodes = Some(nodes.parse().ok()?),
+            _ => return None,
+        }
+    }
+    (limits != SearchLimits::default()).then_some(limits)
 }
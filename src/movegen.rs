@@ -32,6 +32,12 @@ impl Board {
         moves
     }
 
+    pub fn legal_moves(&self) -> MoveList {
+        let mut moves = self.pseudolegal_moves();
+        moves.arr.retain(|entry| self.is_legal(entry.m));
+        moves
+    }
+
     /// Generates all pseudolegal moves
     pub fn generate_moves(&self, gen_type: MGT, moves: &mut MoveList) {
         let mut dests = match gen_type {
@@ -64,31 +70,14 @@ impl Board {
     }
 
     fn castling_moves(&self, moves: &mut MoveList) {
-        if self.stm == Color::White {
-            if self.can_castle(Castle::WhiteKing)
-                && self.threats() & Castle::WhiteKing.check_squares() == Bitboard::EMPTY
-                && self.occupancies() & Castle::WhiteKing.empty_squares() == Bitboard::EMPTY
-            {
-                moves.push(Move::new(Square::E1, Square::G1, MoveType::CastleMove));
-            }
-            if self.can_castle(Castle::WhiteQueen)
-                && self.threats() & Castle::WhiteQueen.check_squares() == Bitboard::EMPTY
-                && self.occupancies() & Castle::WhiteQueen.empty_squares() == Bitboard::EMPTY
-            {
-                moves.push(Move::new(Square::E1, Square::C1, MoveType::CastleMove));
-            }
-        } else {
-            if self.can_castle(Castle::BlackKing)
-                && self.threats() & Castle::BlackKing.check_squares() == Bitboard::EMPTY
-                && self.occupancies() & Castle::BlackKing.empty_squares() == Bitboard::EMPTY
-            {
-                moves.push(Move::new(Square::E8, Square::G8, MoveType::CastleMove));
-            }
-            if self.can_castle(Castle::BlackQueen)
-                && self.threats() & Castle::BlackQueen.check_squares() == Bitboard::EMPTY
-                && self.occupancies() & Castle::BlackQueen.empty_squares() == Bitboard::EMPTY
-            {
-                moves.push(Move::new(Square::E8, Square::C8, MoveType::CastleMove));
+        let castles = match self.stm {
+            Color::White => [Castle::WhiteKing, Castle::WhiteQueen],
+            Color::Black => [Castle::BlackKing, Castle::BlackQueen],
+        };
+        for castle in castles {
+            if self.can_castle(castle) && self.castle_path_is_safe(castle) {
+                let king = self.king_square(self.stm);
+                moves.push(Move::new(king, self.castling_rook(castle), MoveType::CastleMove));
             }
         }
     }
@@ -64,7 +64,7 @@ impl MovePicker {
         if self.phase == MovePickerPhase::TTMove {
             self.phase = MovePickerPhase::CapturesInit;
             if let Some(tt_move) = self.tt_move {
-                if board.occupancies().empty(tt_move.to()) && self.skip_quiets {
+                if !tt_move.is_capture(board) && self.skip_quiets {
                     return self.next(board, td);
                 }
                 if board.is_pseudo_legal(self.tt_move) {
@@ -30,7 +30,7 @@ impl Board {
                 total += count;
 
                 if ROOT {
-                    println!("{}: {count}", m.to_san());
+                    println!("{}: {count}", m.to_uci());
                 }
             }
         }
@@ -45,20 +45,26 @@ mod movegen_tests {
 
     use crate::board::Board;
 
-    #[test]
-    pub fn epd_perft() {
-        let file = BufReader::new(File::open("./src/ethereal_perft.epd").expect("File not found"));
+    /// Checks every position of the suite up to `max_depth`
+    fn epd_suite(path: &str, max_depth: usize) {
+        let file = BufReader::new(File::open(path).expect("File not found"));
         let vec = file.lines().collect::<Vec<_>>();
         thread::scope(|s| {
             vec.iter().enumerate().for_each(|(test_num, line)| {
                 s.spawn(move || {
                     let l = line.as_ref().unwrap().clone();
+                    if l.starts_with('#') {
+                        return;
+                    }
                     let vec = l.split(" ;").collect::<Vec<&str>>();
                     let mut iter = vec.iter();
                     let board = Board::from_fen(iter.next().unwrap());
                     for entry in iter {
                         let (depth, nodes) = entry.split_once(' ').unwrap();
                         let depth = depth[1..].parse::<usize>().unwrap();
+                        if depth > max_depth {
+                            continue;
+                        }
                         let nodes = nodes.parse::<usize>().unwrap();
                         eprintln!("test {test_num}: depth {depth} expected {nodes}");
                         assert_eq!(nodes, board.perft(depth), "Test number {test_num} failed");
@@ -69,6 +75,22 @@ mod movegen_tests {
         });
     }
 
+    #[test]
+    pub fn epd_perft() {
+        epd_suite("./src/ethereal_perft.epd", usize::MAX);
+    }
+
+    #[test]
+    pub fn chess960_perft() {
+        epd_suite("./src/fischer_perft.epd", 4);
+    }
+
+    #[test]
+    #[ignore = "searches every chess960 position to its full depth"]
+    pub fn chess960_perft_deep() {
+        epd_suite("./src/fischer_perft.epd", usize::MAX);
+    }
+
     #[test]
     pub fn berky_perft() {
         thread::scope(|s| {
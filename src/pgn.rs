@@ -0,0 +1,625 @@
+use std::{fmt, fs, iter::Peekable, str::Chars};
+
+use crate::{
+    board::Board,
+    chess_move::Move,
+    fen::{FenError, STARTING_FEN},
+    san::SanError,
+    search::search::{CHECKMATE, NEAR_CHECKMATE},
+    types::pieces::Color,
+};
+
+/// Tokens that end the movetext of a game
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+/// Traditional move suffix annotations and the NAGs they stand for
+const SUFFIX_NAGS: [(&str, u8); 6] = [("!", 1), ("?", 2), ("!!", 3), ("??", 4), ("!?", 5), ("?!", 6)];
+
+/// Lines of exported movetext are kept below this length
+const MAX_LINE_LEN: usize = 80;
+
+/// Reasons a PGN can be rejected. Line numbers refer to the line of the PGN the problem was found on.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PgnError {
+    /// A tag pair wasn't of the form `[Name "Value"]`
+    InvalidTag(usize),
+    InvalidFen(FenError),
+    InvalidMove {
+        line: usize,
+        error: SanError,
+    },
+    UnexpectedToken {
+        line: usize,
+        token: String,
+    },
+    UnterminatedComment(usize),
+    UnterminatedVariation(usize),
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidTag(line) => write!(f, "line {line}: invalid tag pair"),
+            Self::InvalidFen(e) => write!(f, "invalid FEN tag: {e}"),
+            Self::InvalidMove { line, error } => write!(f, "line {line}: {error}"),
+            Self::UnexpectedToken { line, token } => write!(f, "line {line}: unexpected '{token}'"),
+            Self::UnterminatedComment(line) => write!(f, "line {line}: comment is never closed"),
+            Self::UnterminatedVariation(line) => write!(f, "line {line}: variation is never closed"),
+        }
+    }
+}
+
+/// A sequence of moves, either the main line of a game or a variation branching off of it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Line {
+    /// Comment before the first move of the line
+    pub comment: Option<String>,
+    pub moves: Vec<PgnMove>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PgnMove {
+    pub m: Move,
+    /// Numeric annotation glyphs, such as 2 for a mistake or 4 for a blunder
+    pub nags: Vec<u8>,
+    /// Comment following the move
+    pub comment: Option<String>,
+    /// Lines played instead of this move, each starting from the position before it
+    pub variations: Vec<Line>,
+}
+
+impl PgnMove {
+    pub fn new(m: Move) -> Self {
+        Self { m, nags: Vec::new(), comment: None, variations: Vec::new() }
+    }
+
+    pub fn add_comment(&mut self, text: &str) {
+        append_comment(&mut self.comment, text);
+    }
+}
+
+fn append_comment(comment: &mut Option<String>, text: &str) {
+    match comment {
+        Some(c) => {
+            c.push(' ');
+            c.push_str(text);
+        }
+        None => *comment = Some(text.to_string()),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Game {
+    /// Tag pairs in the order they are written
+    pub tags: Vec<(String, String)>,
+    /// Position the game starts from, given by the FEN tag if there is one
+    pub start: Board,
+    pub mainline: Line,
+    pub result: String,
+}
+
+impl Game {
+    /// A game without any moves, with the seven tags every PGN is expected to have
+    pub fn new(start: Board) -> Self {
+        let mut game = Self { tags: Vec::new(), start, mainline: Line::default(), result: String::new() };
+        for (name, value) in
+            [("Event", "?"), ("Site", "?"), ("Date", "????.??.??"), ("Round", "?"), ("White", "?"), ("Black", "?")]
+        {
+            game.set_tag(name, value);
+        }
+        game.set_result("*");
+        if start != Board::from_fen(STARTING_FEN) {
+            game.set_tag("SetUp", "1");
+            game.set_tag("FEN", &start.to_fen());
+        }
+        game
+    }
+
+    /// Replaces the value of a tag, or adds the tag if the game doesn't have it yet
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn set_result(&mut self, result: &str) {
+        self.result = result.to_string();
+        self.set_tag("Result", result);
+    }
+
+    /// Every move of the main line along with the position it was played in
+    pub fn mainline_positions(&self) -> impl Iterator<Item = (Board, &PgnMove)> {
+        self.mainline.moves.iter().scan(self.start, |board, node| {
+            let before = *board;
+            *board = board.make_move(node.m);
+            Some((before, node))
+        })
+    }
+
+    /// Move number of the move played at `ply` plies into the game, and whether white plays it
+    fn move_number(&self, ply: usize) -> (usize, bool) {
+        let plies = ply + usize::from(self.start.stm == Color::Black);
+        (usize::from(self.start.num_moves) + plies / 2, plies.is_multiple_of(2))
+    }
+
+    fn movetext(&self, line: &Line, mut board: Board, mut ply: usize, tokens: &mut Vec<String>) {
+        if let Some(comment) = &line.comment {
+            tokens.push(comment_token(comment));
+        }
+        let mut numbered = false;
+        for node in &line.moves {
+            let (number, white) = self.move_number(ply);
+            if white {
+                tokens.push(format!("{number}."));
+            } else if !numbered {
+                tokens.push(format!("{number}..."));
+            }
+            tokens.push(board.san(node.m));
+            tokens.extend(node.nags.iter().map(|nag| format!("${nag}")));
+            numbered = true;
+
+            if let Some(comment) = &node.comment {
+                tokens.push(comment_token(comment));
+                numbered = false;
+            }
+            for variation in &node.variations {
+                tokens.push("(".to_string());
+                self.movetext(variation, board, ply, tokens);
+                tokens.push(")".to_string());
+                numbered = false;
+            }
+
+            board = board.make_move(node.m);
+            ply += 1;
+        }
+    }
+}
+
+fn comment_token(comment: &str) -> String {
+    format!("{{{}}}", comment.replace('}', ""))
+}
+
+impl fmt::Display for Game {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in &self.tags {
+            writeln!(f, "[{name} \"{}\"]", value.replace('\\', "\\\\").replace('"', "\\\""))?;
+        }
+        writeln!(f)?;
+
+        let mut tokens = Vec::new();
+        self.movetext(&self.mainline, self.start, 0, &mut tokens);
+        tokens.push(self.result.clone());
+
+        let mut line = String::new();
+        let mut prev = "";
+        for token in &tokens {
+            let sep = if line.is_empty() || prev == "(" || token == ")" { "" } else { " " };
+            if !line.is_empty() && line.len() + sep.len() + token.len() > MAX_LINE_LEN {
+                writeln!(f, "{line}")?;
+                line.clear();
+            } else {
+                line += sep;
+            }
+            line += token;
+            prev = token;
+        }
+        writeln!(f, "{line}")
+    }
+}
+
+/// Comment recording an evaluation in the format most GUIs understand, where `score` is from
+/// white's point of view
+pub fn eval_comment(score: i32) -> String {
+    if score >= NEAR_CHECKMATE {
+        format!("[%eval #{}]", (CHECKMATE - score + 1) / 2)
+    } else if score <= -NEAR_CHECKMATE {
+        format!("[%eval #-{}]", (CHECKMATE + score + 1) / 2)
+    } else {
+        format!("[%eval {:.2}]", f64::from(score) / 100.)
+    }
+}
+
+/// Prints the UCI position command for every position in the main line of each game in a PGN file,
+/// so games can be replayed move by move
+pub fn convert(path: &str) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("couldn't read {path}: {e}"))?;
+    for game in parse_pgn(&text).map_err(|e| e.to_string())? {
+        let mut command = format!("position fen {} moves", game.start.to_fen());
+        println!("{command}");
+        for node in &game.mainline.moves {
+            command.push(' ');
+            command += &node.m.to_uci();
+            println!("{command}");
+        }
+    }
+    Ok(())
+}
+
+/// Reads every game in a PGN
+pub fn parse_pgn(text: &str) -> Result<Vec<Game>, PgnError> {
+    let mut parser =
+        Parser { chars: text.chars().peekable(), line: 1, line_start: true, peeked: None, pending_nag: None };
+    let mut games = Vec::new();
+    while let Some(game) = parser.game()? {
+        games.push(game);
+    }
+    Ok(games)
+}
+
+#[derive(Debug)]
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    Nag(u8),
+    Move(String),
+    Result(String),
+    OpenVariation,
+    CloseVariation,
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    /// Line the parser is currently on
+    line: usize,
+    /// Whether the next character is the first of its line
+    line_start: bool,
+    peeked: Option<Token>,
+    /// Suffix annotation split off of the last move, to be handed out as the next token
+    pending_nag: Option<u8>,
+}
+
+impl Parser<'_> {
+    fn game(&mut self) -> Result<Option<Game>, PgnError> {
+        let mut tags = Vec::new();
+        while let Some(Token::Tag(..)) = self.peek()? {
+            let Some(Token::Tag(name, value)) = self.next_token()? else { unreachable!() };
+            tags.push((name, value));
+        }
+        if tags.is_empty() && self.peek()?.is_none() {
+            return Ok(None);
+        }
+
+        let start = match tags.iter().find(|(name, _)| name == "FEN") {
+            Some((_, fen)) => Board::try_from_fen(fen).map_err(PgnError::InvalidFen)?,
+            None => Board::from_fen(STARTING_FEN),
+        };
+        let mainline = self.line(start)?;
+        let result = match self.next_token()? {
+            Some(Token::Result(result)) => result,
+            Some(Token::CloseVariation) => {
+                return Err(PgnError::UnexpectedToken { line: self.line, token: ")".to_string() })
+            }
+            // Movetext without a result, which some tools write for unfinished games
+            token => {
+                self.peeked = token;
+                tags.iter().find(|(name, _)| name == "Result").map_or("*".to_string(), |(_, result)| result.clone())
+            }
+        };
+
+        Ok(Some(Game { tags, start, mainline, result }))
+    }
+
+    /// Reads moves starting from `board` until the end of the line, which is left to the caller
+    fn line(&mut self, mut board: Board) -> Result<Line, PgnError> {
+        let mut line = Line::default();
+        let mut before = board;
+        loop {
+            if matches!(self.peek()?, None | Some(Token::Tag(..) | Token::Result(_) | Token::CloseVariation)) {
+                return Ok(line);
+            }
+            match self.next_token()?.unwrap() {
+                Token::Move(san) => {
+                    let m = board.parse_san(&san).map_err(|error| PgnError::InvalidMove { line: self.line, error })?;
+                    line.moves.push(PgnMove::new(m));
+                    before = board;
+                    board = board.make_move(m);
+                }
+                Token::Nag(nag) => {
+                    let Some(last) = line.moves.last_mut() else {
+                        return Err(PgnError::UnexpectedToken { line: self.line, token: format!("${nag}") });
+                    };
+                    last.nags.push(nag);
+                }
+                Token::Comment(text) => match line.moves.last_mut() {
+                    Some(last) => last.add_comment(&text),
+                    None => append_comment(&mut line.comment, &text),
+                },
+                Token::OpenVariation => {
+                    let start_line = self.line;
+                    let Some(last) = line.moves.last_mut() else {
+                        return Err(PgnError::UnexpectedToken { line: self.line, token: "(".to_string() });
+                    };
+                    let variation = self.line(before)?;
+                    if !matches!(self.next_token()?, Some(Token::CloseVariation)) {
+                        return Err(PgnError::UnterminatedVariation(start_line));
+                    }
+                    last.variations.push(variation);
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    fn peek(&mut self) -> Result<Option<&Token>, PgnError> {
+        if self.peeked.is_none() {
+            self.peeked = self.read_token()?;
+        }
+        Ok(self.peeked.as_ref())
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, PgnError> {
+        match self.peeked.take() {
+            Some(token) => Ok(Some(token)),
+            None => self.read_token(),
+        }
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+        }
+        self.line_start = c == Some('\n');
+        c
+    }
+
+    fn read_token(&mut self) -> Result<Option<Token>, PgnError> {
+        if let Some(nag) = self.pending_nag.take() {
+            return Ok(Some(Token::Nag(nag)));
+        }
+        loop {
+            let line_start = self.line_start;
+            let Some(c) = self.next_char() else { return Ok(None) };
+            match c {
+                c if c.is_whitespace() => (),
+                '[' => return self.tag().map(Some),
+                '{' => {
+                    let start_line = self.line;
+                    let mut text = String::new();
+                    loop {
+                        match self.next_char() {
+                            Some('}') => break,
+                            Some(c) => text.push(c),
+                            None => return Err(PgnError::UnterminatedComment(start_line)),
+                        }
+                    }
+                    return Ok(Some(Token::Comment(text.split_whitespace().collect::<Vec<_>>().join(" "))));
+                }
+                // Rest of line comments and escaped lines carry nothing worth keeping. Only a % in the
+                // first column escapes its line.
+                ';' => while self.next_char().is_some_and(|c| c != '\n') {},
+                '%' if line_start => while self.next_char().is_some_and(|c| c != '\n') {},
+                '(' => return Ok(Some(Token::OpenVariation)),
+                ')' => return Ok(Some(Token::CloseVariation)),
+                '$' => {
+                    let digits = self.take_word();
+                    return digits
+                        .parse()
+                        .map(|nag| Some(Token::Nag(nag)))
+                        .map_err(|_| PgnError::UnexpectedToken { line: self.line, token: format!("${digits}") });
+                }
+                c => {
+                    let mut word = c.to_string();
+                    word += &self.take_word();
+                    if let Some(token) = self.word(&word)? {
+                        return Ok(Some(token));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Characters up to the next whitespace or delimiter
+    fn take_word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if c.is_whitespace() || "{}()[];$".contains(c) {
+                break;
+            }
+            word.push(c);
+            self.chars.next();
+        }
+        word
+    }
+
+    /// Turns a word of movetext into a token, skipping over move numbers
+    fn word(&mut self, word: &str) -> Result<Option<Token>, PgnError> {
+        if RESULTS.contains(&word) {
+            return Ok(Some(Token::Result(word.to_string())));
+        }
+        // Move numbers may run straight into the move that follows them, as in `1.e4`
+        let unnumbered = word.trim_start_matches(|c: char| c.is_ascii_digit());
+        let word = if unnumbered.starts_with('.') { unnumbered.trim_start_matches('.') } else { word };
+        if word.is_empty() {
+            return Ok(None);
+        }
+
+        let (san, suffix) = word.split_at(word.find(['!', '?']).unwrap_or(word.len()));
+        if !suffix.is_empty() {
+            let nag = SUFFIX_NAGS.iter().find(|(s, _)| *s == suffix).map(|&(_, nag)| nag);
+            self.pending_nag =
+                Some(nag.ok_or_else(|| PgnError::UnexpectedToken { line: self.line, token: suffix.to_string() })?);
+        }
+        Ok(Some(Token::Move(san.to_string())))
+    }
+
+    /// Reads a tag pair once its opening bracket has been consumed
+    fn tag(&mut self) -> Result<Token, PgnError> {
+        let invalid = PgnError::InvalidTag(self.line);
+        let name = self.take_word();
+        while self.chars.next_if(|c| *c == ' ' || *c == '\t').is_some() {}
+        if name.is_empty() || self.next_char() != Some('"') {
+            return Err(invalid);
+        }
+
+        let mut value = String::new();
+        loop {
+            match self.next_char() {
+                Some('"') => break,
+                Some('\\') => value.push(self.next_char().ok_or(PgnError::InvalidTag(self.line))?),
+                Some('\n') | None => return Err(invalid),
+                Some(c) => value.push(c),
+            }
+        }
+
+        while self.chars.next_if(|c| *c == ' ' || *c == '\t').is_some() {}
+        if self.next_char() != Some(']') {
+            return Err(invalid);
+        }
+        Ok(Token::Tag(name, value))
+    }
+}
+
+#[cfg(test)]
+mod pgn_tests {
+    use super::{eval_comment, parse_pgn, Game, PgnError, PgnMove};
+    use crate::{board::Board, chess_move::Move, fen::STARTING_FEN, san::SanError, search::search::CHECKMATE};
+
+    fn tag<'a>(game: &'a Game, name: &str) -> Option<&'a str> {
+        game.tags.iter().find(|(n, _)| n == name).map(|(_, value)| value.as_str())
+    }
+
+    const SAMPLE: &str = r#"[Event "Casual \"blitz\" game"]
+[Site "?"]
+[White "Titan"]
+[Black "Human"]
+[Result "1-0"]
+
+{Scholar's mate} 1. e4 e5 2. Bc4!? Nc6 (2... Nf6 3. d3 {solid}) 3. Qh5 $6 Nf6?? ; the losing move
+4. Qxf7# 1-0
+
+[Event "Second"]
+[SetUp "1"]
+[FEN "4k3/8/8/8/8/8/8/R3K3 w Q - 0 40"]
+
+40.O-O-O Kf7 *
+"#;
+
+    #[test]
+    fn parse() {
+        let games = parse_pgn(SAMPLE).unwrap();
+        assert_eq!(games.len(), 2);
+
+        let game = &games[0];
+        assert_eq!(tag(game, "Event"), Some("Casual \"blitz\" game"));
+        assert_eq!(tag(game, "White"), Some("Titan"));
+        assert_eq!(game.result, "1-0");
+        assert_eq!(game.mainline.comment.as_deref(), Some("Scholar's mate"));
+
+        let moves = &game.mainline.moves;
+        assert_eq!(moves.len(), 7);
+        assert_eq!(moves[2].nags, [5]);
+        assert_eq!(moves[4].nags, [6]);
+        assert_eq!(moves[5].nags, [4]);
+
+        assert_eq!(moves[3].variations.len(), 1);
+        let variation = &moves[3].variations[0];
+        assert_eq!(variation.moves.len(), 2);
+        assert_eq!(variation.moves[1].comment.as_deref(), Some("solid"));
+
+        let (board, last) = game.mainline_positions().last().unwrap();
+        assert_eq!(board.san(last.m), "Qxf7#");
+
+        let game = &games[1];
+        assert_eq!(game.start, Board::from_fen("4k3/8/8/8/8/8/8/R3K3 w Q - 0 40"));
+        assert_eq!(game.result, "*");
+        assert!(game.mainline.moves[0].m.is_castle());
+    }
+
+    #[test]
+    fn write() {
+        let games = parse_pgn(SAMPLE).unwrap();
+        let expected = r#"[Event "Casual \"blitz\" game"]
+[Site "?"]
+[White "Titan"]
+[Black "Human"]
+[Result "1-0"]
+
+{Scholar's mate} 1. e4 e5 2. Bc4 $5 Nc6 (2... Nf6 3. d3 {solid}) 3. Qh5 $6 Nf6
+$4 4. Qxf7# 1-0
+"#;
+        assert_eq!(games[0].to_string(), expected);
+
+        for game in &games {
+            assert_eq!(&parse_pgn(&game.to_string()).unwrap()[0], game);
+        }
+    }
+
+    #[test]
+    fn new_game() {
+        let board = Board::from_fen(STARTING_FEN);
+        let mut game = Game::new(board);
+        let m = board.parse_san("d4").unwrap();
+        game.mainline.moves.push(PgnMove::new(m));
+        game.mainline.moves[0].add_comment(&eval_comment(31));
+        game.set_tag("White", "Titan");
+        game.set_result("1/2-1/2");
+
+        let text = game.to_string();
+        assert!(
+            text.starts_with("[Event \"?\"]\n[Site \"?\"]\n[Date \"????.??.??\"]\n[Round \"?\"]\n[White \"Titan\"]")
+        );
+        assert!(text.ends_with("[Result \"1/2-1/2\"]\n\n1. d4 {[%eval 0.31]} 1/2-1/2\n"));
+        assert!(tag(&game, "FEN").is_none());
+
+        let game = Game::new(Board::from_fen("4k3/8/8/8/8/8/8/R3K3 b Q - 0 40"));
+        assert_eq!(tag(&game, "FEN"), Some("4k3/8/8/8/8/8/8/R3K3 b Q - 0 40"));
+        assert_eq!(game.move_number(0), (40, false));
+        assert_eq!(game.move_number(1), (41, true));
+    }
+
+    #[test]
+    fn long_games_wrap() {
+        let mut board = Board::from_fen(STARTING_FEN);
+        let mut game = Game::new(board);
+        for san in ["Nf3", "Nf6", "Ng1", "Ng8"].iter().cycle().take(60) {
+            let m = board.parse_san(san).unwrap();
+            game.mainline.moves.push(PgnMove::new(m));
+            board = board.make_move(m);
+        }
+        let text = game.to_string();
+        assert!(text.lines().all(|line| line.len() <= 80));
+        assert_eq!(parse_pgn(&text).unwrap()[0], game);
+    }
+
+    #[test]
+    fn eval_comments() {
+        assert_eq!(eval_comment(-5), "[%eval -0.05]");
+        assert_eq!(eval_comment(CHECKMATE - 5), "[%eval #3]");
+        assert_eq!(eval_comment(-CHECKMATE + 4), "[%eval #-2]");
+        // Scores are from white's point of view, so black can be the one about to deliver mate
+        assert_eq!(eval_comment(-CHECKMATE + 1), "[%eval #-1]");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            parse_pgn("[Event \"?\"]\n\n1. e4 e5 2. Ke3 *"),
+            Err(PgnError::InvalidMove { line: 3, error: SanError::Illegal("Ke3".to_string()) })
+        );
+        assert_eq!(parse_pgn("[Event ?]\n1. e4 *"), Err(PgnError::InvalidTag(1)));
+        assert_eq!(parse_pgn("1. e4 {unfinished\n\n"), Err(PgnError::UnterminatedComment(1)));
+        assert_eq!(parse_pgn("1. e4 (1. d4 d5\n"), Err(PgnError::UnterminatedVariation(1)));
+        assert_eq!(parse_pgn("1. e4 e5) *"), Err(PgnError::UnexpectedToken { line: 1, token: ")".to_string() }));
+        assert!(matches!(parse_pgn("[FEN \"8/8/8/8/8/8/8/8 w - - 0 1\"]\n*"), Err(PgnError::InvalidFen(_))));
+        assert_eq!(parse_pgn("  \n").unwrap(), []);
+    }
+
+    #[test]
+    fn escaped_lines() {
+        let games = parse_pgn("% exported by some GUI\n1. e4 e5\n%2. d4\n2. Nf3 *").unwrap();
+        assert_eq!(games[0].mainline.moves.len(), 3);
+        // Anywhere but the first column a % is just an unknown token
+        assert!(matches!(parse_pgn("1. e4 %e5\n*"), Err(PgnError::InvalidMove { line: 1, .. })));
+        assert!(matches!(parse_pgn(" % e5\n*"), Err(PgnError::InvalidMove { line: 1, .. })));
+    }
+
+    #[test]
+    fn moves_are_checked_against_the_board() {
+        let games = parse_pgn("1. e4 e5 2. Nf3 Nc6 3. Bb5 a6 *").unwrap();
+        let board = Board::from_fen(STARTING_FEN);
+        assert_eq!(games[0].mainline.moves[0].m, Move::from_uci("e2e4", &board));
+        assert_eq!(games[0].mainline_positions().count(), 6);
+    }
+}
@@ -0,0 +1,285 @@
+use std::fmt;
+
+use crate::{
+    board::Board,
+    chess_move::{Castle, Move},
+    types::{
+        pieces::{Color, Piece, PieceName},
+        square::{Square, SQUARE_NAMES},
+    },
+};
+
+/// Reasons a move in standard algebraic notation can be rejected
+#[derive(Debug, PartialEq, Eq)]
+pub enum SanError {
+    /// The move isn't written in standard algebraic notation
+    Malformed(String),
+    /// No legal move in the position matches
+    Illegal(String),
+    /// More than one legal move matches, so the move needed more disambiguation
+    Ambiguous(String),
+}
+
+impl fmt::Display for SanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed(san) => write!(f, "malformed move '{san}'"),
+            Self::Illegal(san) => write!(f, "illegal move '{san}'"),
+            Self::Ambiguous(san) => write!(f, "ambiguous move '{san}'"),
+        }
+    }
+}
+
+impl Board {
+    /// Writes a legal move in standard algebraic notation, e.g. Nbd7, exd6, O-O or Qxf7#
+    pub fn san(&self, m: Move) -> String {
+        let mut str = if m.is_castle() {
+            match m.castle_type() {
+                Castle::WhiteKing | Castle::BlackKing => "O-O".to_string(),
+                Castle::WhiteQueen | Castle::BlackQueen => "O-O-O".to_string(),
+            }
+        } else {
+            self.san_body(m)
+        };
+
+        let next = self.make_move(m);
+        if next.in_check() {
+            str += if next.legal_moves().len() == 0 { "#" } else { "+" };
+        }
+        str
+    }
+
+    /// Everything but the check suffix of a move that isn't a castle
+    fn san_body(&self, m: Move) -> String {
+        let piece = self.piece_at(m.from()).name();
+        let capture = m.is_capture(self) || m.is_en_passant();
+        let mut str = String::new();
+
+        if piece == PieceName::Pawn {
+            if capture {
+                str.push(file_char(m.from()));
+            }
+        } else {
+            str += &Piece::new(piece, Color::White).char();
+            // Other pieces of the same type that could also reach the destination force us to say
+            // which one is moving, by file if that is enough, then by rank, then by both
+            let others = self
+                .legal_moves()
+                .iter()
+                .filter(|o| {
+                    !o.is_castle()
+                        && o.to() == m.to()
+                        && o.from() != m.from()
+                        && self.piece_at(o.from()).name() == piece
+                })
+                .collect::<Vec<_>>();
+            if !others.is_empty() {
+                if others.iter().all(|o| o.from().file() != m.from().file()) {
+                    str.push(file_char(m.from()));
+                } else if others.iter().all(|o| o.from().rank() != m.from().rank()) {
+                    str.push(rank_char(m.from()));
+                } else {
+                    str += SQUARE_NAMES[m.from()];
+                }
+            }
+        }
+
+        if capture {
+            str.push('x');
+        }
+        str += SQUARE_NAMES[m.to()];
+        if let Some(promo) = m.promotion() {
+            str.push('=');
+            str += &Piece::new(promo, Color::White).char();
+        }
+        str
+    }
+
+    /// Finds the legal move written in standard algebraic notation. Check and mate markers,
+    /// annotations such as `!?` and an `e.p.` suffix are optional, and castles may be written with
+    /// zeros.
+    pub fn parse_san(&self, san: &str) -> Result<Move, SanError> {
+        let malformed = || SanError::Malformed(san.to_string());
+        let trimmed = san.trim();
+        let trimmed = trimmed.strip_suffix("e.p.").unwrap_or(trimmed).trim_end();
+        let trimmed = trimmed.trim_end_matches(['+', '#', '!', '?']);
+
+        let candidates = self.legal_moves();
+        let mut matches = match trimmed {
+            "O-O" | "0-0" => {
+                candidates.iter().filter(|m| m.is_castle() && m.to().file() > m.from().file()).collect::<Vec<_>>()
+            }
+            "O-O-O" | "0-0-0" => {
+                candidates.iter().filter(|m| m.is_castle() && m.to().file() < m.from().file()).collect::<Vec<_>>()
+            }
+            _ => {
+                let pattern = SanPattern::parse(trimmed).ok_or_else(malformed)?;
+                candidates.iter().filter(|&m| pattern.matches(self, m)).collect::<Vec<_>>()
+            }
+        };
+
+        match matches.len() {
+            0 => Err(SanError::Illegal(san.to_string())),
+            1 => Ok(matches.remove(0)),
+            _ => Err(SanError::Ambiguous(san.to_string())),
+        }
+    }
+}
+
+/// The parts of a non castling move that can be read from its notation alone
+struct SanPattern {
+    piece: PieceName,
+    from_file: Option<u32>,
+    from_rank: Option<u32>,
+    to: Square,
+    promotion: Option<PieceName>,
+}
+
+impl SanPattern {
+    fn parse(san: &str) -> Option<Self> {
+        let mut chars = san.chars().collect::<Vec<_>>();
+
+        let promotion = match chars.as_slice() {
+            [.., '=', p] | [.., '1'..='8', p] if p.is_ascii_uppercase() => Some(piece_name(*p)?),
+            _ => None,
+        };
+        if promotion.is_some() {
+            chars.pop();
+            if chars.last() == Some(&'=') {
+                chars.pop();
+            }
+        }
+
+        let rank = chars.pop().and_then(rank_index)?;
+        let file = chars.pop().and_then(file_index)?;
+        let to = Square(rank * 8 + file);
+        if chars.last() == Some(&'x') {
+            chars.pop();
+        }
+
+        let mut rest = chars.as_slice();
+        let piece = match rest.first() {
+            Some(&c) if c.is_ascii_uppercase() => {
+                rest = &rest[1..];
+                piece_name(c)?
+            }
+            _ => PieceName::Pawn,
+        };
+        let (from_file, from_rank) = match rest {
+            [] => (None, None),
+            [c] => match (file_index(*c), rank_index(*c)) {
+                (Some(f), _) => (Some(f), None),
+                (_, Some(r)) => (None, Some(r)),
+                _ => return None,
+            },
+            [f, r] => (Some(file_index(*f)?), Some(rank_index(*r)?)),
+            _ => return None,
+        };
+
+        Some(Self { piece, from_file, from_rank, to, promotion })
+    }
+
+    fn matches(&self, board: &Board, m: Move) -> bool {
+        !m.is_castle()
+            && m.to() == self.to
+            && board.piece_at(m.from()).name() == self.piece
+            && m.promotion() == self.promotion
+            && self.from_file.is_none_or(|f| m.from().file() == f)
+            && self.from_rank.is_none_or(|r| m.from().rank() == r)
+    }
+}
+
+fn piece_name(c: char) -> Option<PieceName> {
+    match c {
+        'N' => Some(PieceName::Knight),
+        'B' => Some(PieceName::Bishop),
+        'R' => Some(PieceName::Rook),
+        'Q' => Some(PieceName::Queen),
+        'K' => Some(PieceName::King),
+        _ => None,
+    }
+}
+
+fn file_index(c: char) -> Option<u32> {
+    ('a'..='h').contains(&c).then(|| c as u32 - 'a' as u32)
+}
+
+fn rank_index(c: char) -> Option<u32> {
+    ('1'..='8').contains(&c).then(|| c as u32 - '1' as u32)
+}
+
+fn file_char(sq: Square) -> char {
+    (b'a' + sq.file() as u8) as char
+}
+
+fn rank_char(sq: Square) -> char {
+    (b'1' + sq.rank() as u8) as char
+}
+
+#[cfg(test)]
+mod san_tests {
+    use super::SanError;
+    use crate::{board::Board, chess_move::Move, fen::STARTING_FEN};
+
+    fn san(fen: &str, uci: &str) -> String {
+        let board = Board::from_fen(fen);
+        board.san(Move::from_uci(uci, &board))
+    }
+
+    #[test]
+    fn write_san() {
+        assert_eq!(san(STARTING_FEN, "e2e4"), "e4");
+        assert_eq!(san(STARTING_FEN, "g1f3"), "Nf3");
+        // Knights on b8 and f6 can both reach d7
+        assert_eq!(san("rnbqkb1r/ppp1pppp/3p1n2/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1", "b8d7"), "Nbd7");
+        // Rooks on a1 and a5 share a file, so the rank is used instead
+        assert_eq!(san("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1", "a1a3"), "R1a3");
+        // Three queens reaching e1 need both file and rank
+        assert_eq!(san("8/k7/8/8/4Q2Q/8/8/K6Q w - - 0 1", "h4e1"), "Qh4e1");
+        assert_eq!(san("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), "exd6");
+        assert_eq!(san("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1g1"), "O-O");
+        assert_eq!(san("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "e8c8"), "O-O-O");
+        assert_eq!(san("rnbqkbnr/pppp1ppp/8/4p3/4P3/5Q2/PPPP1PPP/RNB1KBNR w KQkq - 0 1", "f3f7"), "Qxf7+");
+        assert_eq!(san("r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR w KQkq - 0 1", "f3f7"), "Qxf7#");
+        assert_eq!(san("8/4P3/8/8/8/8/k7/4K3 w - - 0 1", "e7e8q"), "e8=Q");
+        assert_eq!(san("3r4/4P3/8/8/8/8/k7/4K3 w - - 0 1", "e7d8n"), "exd8=N");
+    }
+
+    #[test]
+    fn parse_san() {
+        let board = Board::from_fen("rnbqkb1r/ppp1pppp/3p1n2/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1");
+        assert_eq!(board.parse_san("Nbd7"), Ok(Move::from_uci("b8d7", &board)));
+        assert_eq!(board.parse_san("N8d7"), Ok(Move::from_uci("b8d7", &board)));
+        assert_eq!(board.parse_san("Nxe4!?"), Ok(Move::from_uci("f6e4", &board)));
+        assert_eq!(board.parse_san("Nd7"), Err(SanError::Ambiguous("Nd7".to_string())));
+        assert_eq!(board.parse_san("Nd6"), Err(SanError::Illegal("Nd6".to_string())));
+        assert_eq!(board.parse_san("Xe4"), Err(SanError::Malformed("Xe4".to_string())));
+        assert_eq!(board.parse_san(""), Err(SanError::Malformed(String::new())));
+
+        let board = Board::from_fen("4k3/8/8/3pP3/8/8/8/R3K3 w Q d6 0 1");
+        assert_eq!(board.parse_san("exd6 e.p."), Ok(Move::from_uci("e5d6", &board)));
+        assert_eq!(board.parse_san("0-0-0"), Ok(Move::from_uci("e1c1", &board)));
+        assert_eq!(board.parse_san("O-O"), Err(SanError::Illegal("O-O".to_string())));
+
+        let board = Board::from_fen("8/4P3/8/8/8/8/k7/4K3 w - - 0 1");
+        assert_eq!(board.parse_san("e8=Q+"), Ok(Move::from_uci("e7e8q", &board)));
+        assert_eq!(board.parse_san("e8N"), Ok(Move::from_uci("e7e8n", &board)));
+        assert!(board.parse_san("e8").is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        for fen in [
+            STARTING_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+            "1nbbnrkr/p1p1ppp1/3p4/1p3P1p/3Pq2P/8/PPP1P1P1/QNBBNRKR w HFhf - 0 9",
+        ] {
+            let board = Board::from_fen(fen);
+            for m in board.legal_moves().iter() {
+                assert_eq!(board.parse_san(&board.san(m)), Ok(m), "{fen} {m}");
+            }
+        }
+    }
+}
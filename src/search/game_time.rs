@@ -2,9 +2,58 @@ use std::time::{Duration, Instant};
 
 use crate::types::pieces::Color;
 
+/// Fraction of the time allocated to a move that the soft limit targets
 const TIME_FRACTION: f64 = 0.67;
 
-const GUI_DELAY: Duration = Duration::from_millis(25);
+/// Number of iterations in a row the best move or score has to stay the same before the soft limit
+/// stops shrinking any further
+pub const STABILITY_LEVELS: usize = 5;
+/// Distance in centipawns from the running average of previous iterations within which a score
+/// counts as stable
+pub const SCORE_STABILITY_MARGIN: i32 = 10;
+
+/// Time management settings that can be changed through `setoption`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TimeOptions {
+    /// Time lost to communication with the GUI on every move
+    pub move_overhead: Duration,
+    /// Time kept in reserve on the clock and never allocated to a search
+    pub safety_buffer: Duration,
+    /// Percentage by which the soft and hard limits are scaled
+    pub time_usage: u32,
+    /// Percentage the soft limit is scaled by, for each number of iterations in a row the best
+    /// move has stayed the same
+    pub move_stability_scale: [u32; STABILITY_LEVELS],
+    /// Percentage the soft limit is scaled by, for each number of iterations in a row the score has
+    /// stayed within `SCORE_STABILITY_MARGIN` of its running average
+    pub score_stability_scale: [u32; STABILITY_LEVELS],
+}
+
+impl Default for TimeOptions {
+    fn default() -> Self {
+        Self {
+            move_overhead: Duration::from_millis(25),
+            safety_buffer: Duration::from_millis(50),
+            time_usage: 100,
+            // Credit to Alexandria (https://github.com/PGG106/Alexandria, GPL-3.0 like Titan) for the
+            // stability scales, taken from its time manager. Builds with the tune feature expose them
+            // as options so they can be tuned for Titan.
+            move_stability_scale: [243, 135, 109, 88, 68],
+            score_stability_scale: [125, 115, 100, 94, 88],
+        }
+    }
+}
+
+/// Expected number of moves left in a game without a `movestogo`
+const SUDDEN_DEATH_MOVES: u32 = 20;
+
+/// Cap on `movestogo` so a long time control doesn't leave too little time for early moves
+const MAX_MOVES_TO_GO: u32 = 50;
+
+/// Time available for a `go movetime` search once communication delays are accounted for
+pub fn fixed_move_time(movetime: Duration, options: TimeOptions) -> Duration {
+    movetime.saturating_sub(options.move_overhead).max(Duration::from_millis(1))
+}
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Clock {
@@ -18,13 +67,23 @@ pub struct Clock {
     pub rec_time: Duration,
     /// Max time allowable for this search
     pub max_time: Duration,
+    /// Settings the allocation is based on
+    pub options: TimeOptions,
 }
 
 impl Clock {
-    /// Returns true if engine is unlikely to finish another depth of iterative deepening before
-    /// time runs out for this search
-    pub fn soft_termination(&self, search_start: Instant) -> bool {
-        search_start.elapsed() > self.rec_time
+    /// Soft limit once scaled by how settled the search looks. `node_frac` is the fraction of nodes
+    /// spent below the best move, while `move_stability` and `score_stability` are the number of
+    /// iterations in a row the best move and the score have stayed the same. Unstable searches get
+    /// more time, while a clear and stable best move lets us stop early.
+    pub fn soft_limit(&self, depth: i32, node_frac: f64, move_stability: usize, score_stability: usize) -> Duration {
+        let scale = |table: [u32; STABILITY_LEVELS], stability: usize| {
+            f64::from(table[stability.min(STABILITY_LEVELS - 1)]) / 100.
+        };
+        let node_scale = if depth > 9 { ((1.44 - node_frac) * 1.62).min(1.) } else { 1. };
+        let move_scale = scale(self.options.move_stability_scale, move_stability);
+        let score_scale = scale(self.options.score_stability_scale, score_stability);
+        self.rec_time.mul_f64(node_scale * move_scale * score_scale).min(self.max_time)
     }
 
     /// Returns true if engine has used the max time allotted to this search
@@ -32,12 +91,30 @@ impl Clock {
         search_start.elapsed() > self.max_time
     }
 
-    /// Calculates a recommended amount of time to spend on a given search.
+    /// Calculates a recommended amount of time to spend on a given search. With `movestogo` the
+    /// time left is split over the moves remaining until the next time control, otherwise the
+    /// game is assumed to last another `SUDDEN_DEATH_MOVES` moves.
     pub fn recommended_time(&mut self, side: Color) {
-        let clock = (self.time_remaining[side].saturating_sub(GUI_DELAY)).max(GUI_DELAY);
-        let time = clock / 20 + self.time_inc[side] * 3 / 4;
-        self.rec_time = time.mul_f64(TIME_FRACTION);
-        self.max_time = (time * 2).min(self.time_remaining[side]);
+        let options = self.options;
+        let mtg = if self.movestogo > 0 { (self.movestogo as u32).min(MAX_MOVES_TO_GO) } else { 1 };
+        // Every move left before the next time control loses the move overhead
+        let reserve = options.safety_buffer + options.move_overhead * mtg;
+        let clock = self.time_remaining[side].saturating_sub(reserve).max(Duration::from_millis(1));
+        let inc = self.time_inc[side];
+        let scale = f64::from(options.time_usage) / 100.;
+
+        let (time, max_time) = if self.movestogo > 0 {
+            let time = (clock + inc * (mtg - 1)) / mtg;
+            // Always leave time for the remaining moves of this time control
+            let max_time = if mtg == 1 { clock } else { (time * 2).min(clock / 2) };
+            (time.min(clock), max_time)
+        } else {
+            let time = clock / SUDDEN_DEATH_MOVES + inc * 3 / 4;
+            (time, (time * 2).min(clock))
+        };
+
+        self.max_time = max_time.mul_f64(scale).min(clock);
+        self.rec_time = time.mul_f64(TIME_FRACTION * scale).min(self.max_time);
     }
 }
 
@@ -49,6 +126,169 @@ impl Default for Clock {
             movestogo: Default::default(),
             rec_time: Duration::MAX,
             max_time: Duration::MAX,
+            options: TimeOptions::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod game_time_tests {
+    use std::time::Duration;
+
+    use super::{fixed_move_time, Clock, TimeOptions, STABILITY_LEVELS};
+    use crate::types::pieces::Color;
+
+    /// Plays out a game where every search takes the time picked by `spend` and then loses the
+    /// default move overhead to communication. `control` is the number of moves per time control, after
+    /// which `base` is added back to the clock. Returns the time used for each move.
+    fn simulate(
+        base: Duration,
+        inc: Duration,
+        control: Option<u32>,
+        moves: u32,
+        spend: fn(&Clock) -> Duration,
+    ) -> Vec<Duration> {
+        let mut remaining = base;
+        let mut used = Vec::new();
+        for ply in 0..moves {
+            let mut clock = Clock::default();
+            clock.time_remaining[Color::White] = remaining;
+            clock.time_inc[Color::White] = inc;
+            clock.movestogo = control.map_or(0, |c| (c - ply % c) as i32);
+            clock.recommended_time(Color::White);
+
+            let spent = spend(&clock) + clock.options.move_overhead;
+            assert!(spent < remaining, "flagged on move {} with {remaining:?} left", ply + 1);
+            assert!(clock.rec_time <= clock.max_time);
+            remaining = remaining - spent + inc;
+            if control.is_some_and(|c| (ply + 1) % c == 0) {
+                remaining += base;
+            }
+            used.push(spent);
         }
+        used
+    }
+
+    #[test]
+    fn sudden_death() {
+        for spend in [|c: &Clock| c.max_time, |c: &Clock| c.rec_time] {
+            simulate(Duration::from_secs(8), Duration::from_millis(80), None, 300, spend);
+            simulate(Duration::from_secs(180), Duration::from_secs(2), None, 300, spend);
+        }
+        // Without an increment, every move costs at least the move overhead, so only the soft limit is
+        // expected to last a long game
+        simulate(Duration::from_secs(60), Duration::ZERO, None, 120, |c| c.rec_time);
+        simulate(Duration::from_secs(10), Duration::ZERO, None, 80, |c| c.rec_time);
+    }
+
+    #[test]
+    fn repeating_time_control() {
+        // Searches that always run into their hard limit must not lose on time either
+        simulate(Duration::from_secs(90 * 60), Duration::ZERO, Some(40), 120, |c| c.max_time);
+        simulate(Duration::from_secs(60), Duration::ZERO, Some(40), 120, |c| c.max_time);
+        simulate(Duration::from_secs(5), Duration::from_millis(50), Some(10), 100, |c| c.max_time);
+
+        // 40 moves in 90 minutes, repeating
+        let used = simulate(Duration::from_secs(90 * 60), Duration::ZERO, Some(40), 120, |c| c.rec_time);
+        for control in used.chunks(40) {
+            let total: Duration = control.iter().sum();
+            // Most of each control should actually be spent on its moves
+            assert!(total > Duration::from_secs(60 * 60));
+            // Time is spread over the whole control rather than burnt on the first moves
+            assert!(control.iter().min().unwrap() > &Duration::from_secs(10));
+        }
+    }
+
+    #[test]
+    fn movestogo_changes_allocation() {
+        let mut clock = Clock::default();
+        clock.time_remaining[Color::White] = Duration::from_secs(60);
+        clock.movestogo = 30;
+        clock.recommended_time(Color::White);
+        let early = clock.rec_time;
+
+        clock.movestogo = 2;
+        clock.recommended_time(Color::White);
+        assert!(clock.rec_time > early * 10);
+
+        clock.movestogo = 1;
+        clock.recommended_time(Color::White);
+        assert!(clock.max_time <= Duration::from_secs(60) - clock.options.safety_buffer);
+    }
+
+    #[test]
+    fn safety_buffer() {
+        let mut clock = Clock::default();
+        clock.time_remaining[Color::White] = Duration::from_secs(1);
+        clock.movestogo = 1;
+        clock.options.safety_buffer = Duration::from_millis(500);
+        clock.recommended_time(Color::White);
+        assert_eq!(clock.max_time, Duration::from_secs(1) - Duration::from_millis(500) - clock.options.move_overhead);
+    }
+
+    #[test]
+    fn move_overhead() {
+        let options = TimeOptions { move_overhead: Duration::from_millis(100), ..Default::default() };
+        assert_eq!(fixed_move_time(Duration::from_millis(1000), options), Duration::from_millis(900));
+        assert_eq!(fixed_move_time(Duration::from_millis(50), options), Duration::from_millis(1));
+
+        // Each of the remaining moves in the time control needs its own overhead
+        let mut clock = Clock { options, movestogo: 10, ..Default::default() };
+        clock.time_remaining[Color::White] = Duration::from_secs(2);
+        clock.recommended_time(Color::White);
+        let slow = clock.max_time;
+        clock.options = TimeOptions::default();
+        clock.recommended_time(Color::White);
+        assert!(slow < clock.max_time);
+    }
+
+    #[test]
+    fn soft_limit_scaling() {
+        let mut clock = Clock::default();
+        clock.time_remaining[Color::White] = Duration::from_secs(60);
+        clock.recommended_time(Color::White);
+        let base = clock.soft_limit(12, 0.5, 2, 2);
+
+        // Best move keeps changing
+        assert!(clock.soft_limit(12, 0.5, 0, 2) > base);
+        // Best move has been the same for a long time
+        assert!(clock.soft_limit(12, 0.5, 10, 2) < base);
+        assert_eq!(clock.soft_limit(12, 0.5, 10, 2), clock.soft_limit(12, 0.5, 100, 2));
+        // Score keeps swinging between iterations
+        assert!(clock.soft_limit(12, 0.5, 2, 0) > base);
+        // Score has settled
+        assert!(clock.soft_limit(12, 0.5, 2, 10) < base);
+        // Nearly all nodes were spent on the best move
+        assert!(clock.soft_limit(12, 0.95, 2, 2) < base);
+        assert_eq!(clock.soft_limit(5, 0.95, 2, 2), clock.soft_limit(5, 0.5, 2, 2));
+
+        // Scales are percentages of the recommended time
+        clock.options.move_stability_scale = [100; STABILITY_LEVELS];
+        clock.options.score_stability_scale = [50; STABILITY_LEVELS];
+        assert_eq!(clock.soft_limit(5, 0.5, 0, 0), clock.rec_time / 2);
+
+        // However unstable the search, the hard limit is respected
+        clock.options = TimeOptions::default();
+        clock.movestogo = 1;
+        clock.recommended_time(Color::White);
+        assert_eq!(clock.soft_limit(12, 0., 0, 0), clock.max_time);
+    }
+
+    #[test]
+    fn time_usage() {
+        let mut clock = Clock::default();
+        clock.time_remaining[Color::White] = Duration::from_secs(60);
+        clock.recommended_time(Color::White);
+        let (rec_time, max_time) = (clock.rec_time, clock.max_time);
+
+        clock.options.time_usage = 50;
+        clock.recommended_time(Color::White);
+        assert!(clock.rec_time.abs_diff(rec_time / 2) <= Duration::from_millis(1));
+        assert!(clock.max_time.abs_diff(max_time / 2) <= Duration::from_millis(1));
+
+        // The hard limit never exceeds the time left on the clock
+        clock.options.time_usage = 10_000;
+        clock.recommended_time(Color::White);
+        assert!(clock.max_time < Duration::from_secs(60));
     }
 }
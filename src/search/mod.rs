@@ -1,5 +1,8 @@
 use arrayvec::ArrayVec;
-use std::ops::{Index, IndexMut};
+use std::{
+    ops::{Index, IndexMut},
+    time::Duration,
+};
 
 use self::{game_time::Clock, search::MAX_SEARCH_DEPTH};
 use crate::{chess_move::Move, types::pieces::Piece};
@@ -34,7 +37,7 @@ impl Default for PlyEntry {
     }
 }
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct PV {
     pub line: ArrayVec<Option<Move>, { MAX_SEARCH_DEPTH as usize }>,
 }
@@ -47,6 +50,20 @@ impl PV {
     }
 }
 
+/// A root move's score and principal variation as reported through MultiPV
+#[derive(Clone, Default)]
+pub struct PvLine {
+    pub score: i32,
+    pub pv: PV,
+}
+
+impl PvLine {
+    /// Root move the line starts with, if there is one
+    pub fn first_move(&self) -> Option<Move> {
+        self.pv.line.first().copied().flatten()
+    }
+}
+
 #[derive(Clone)]
 pub struct SearchStack {
     stack: [PlyEntry; MAX_SEARCH_DEPTH as usize + 5],
@@ -78,17 +95,18 @@ impl IndexMut<i32> for SearchStack {
     }
 }
 
-#[derive(Clone, Copy, Default, PartialEq, Eq)]
-pub enum SearchType {
-    /// User has requested a search until a particular depth
-    Depth(i32),
+/// Limits given to a search by the GUI. Any combination may be set, and the search stops as soon as
+/// one of them is reached. A search with no limits runs until it is told to stop.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SearchLimits {
+    /// Search until a particular depth
+    pub depth: Option<i32>,
+    /// Only search for N nodes, counted across all threads
+    pub nodes: Option<u64>,
+    /// Search for a mate in N moves
+    pub mate: Option<i32>,
+    /// Search for a fixed amount of time
+    pub movetime: Option<Duration>,
     /// Search determines how much time to allow itself
-    Time(Clock),
-    /// Only search for N nodes
-    Nodes(u64),
-    /// Search for a mate at the provided depth
-    Mate(i32),
-    #[default]
-    /// Search forever
-    Infinite,
+    pub clock: Option<Clock>,
 }
@@ -1,15 +1,16 @@
-use std::cmp::{max, min};
+use std::cmp::{max, min, Reverse};
 use std::time::Instant;
 
 use crate::board::Board;
 use crate::chess_move::Move;
 use crate::movelist::{MoveListEntry, MAX_LEN};
 use crate::movepicker::MovePicker;
+use crate::search::game_time::SCORE_STABILITY_MARGIN;
 use crate::search::SearchStack;
 use crate::thread::ThreadData;
 use crate::transposition::{EntryFlag, TableEntry, TranspositionTable};
 
-use super::PV;
+use super::{PvLine, PV};
 use crate::types::pieces::Piece;
 use arrayvec::ArrayVec;
 
@@ -35,44 +36,84 @@ pub fn start_search(td: &mut ThreadData, print_uci: bool, board: Board, tt: &Tra
 /// the way. As a result, for more expensive depths, we already have a good idea of the best move
 /// and can maximize the efficacy of alpha beta pruning.
 pub fn iterative_deepening(td: &mut ThreadData, board: &Board, print_uci: bool, tt: &TranspositionTable) {
-    let mut pv = PV::default();
-    let mut prev_score = -INFINITY;
     let mut depth = 1;
-
-    loop {
+    td.pv_lines.clear();
+    td.pv_idx = 0;
+    // There's no point asking for more lines than there are root moves to fill them
+    let root_moves = board.legal_moves().iter().filter(|&m| !td.is_excluded_root_move(m)).count();
+    let multi_pv = td.multi_pv.min(root_moves).max(1);
+    // Number of iterations in a row the best move and the score have stayed the same
+    let (mut move_stability, mut score_stability) = (0, 0);
+    let mut prev_move = None;
+    let mut average_score = None;
+
+    'deepening: loop {
         td.sel_depth = 0;
 
-        assert_eq!(0, td.ply);
-        assert_eq!(0, td.accumulators.top);
+        for pv_idx in 0..multi_pv {
+            td.pv_idx = pv_idx;
+            let prev_score = td.pv_lines.get(pv_idx).map_or(-INFINITY, |line| line.score);
+            let mut pv = PV::default();
 
-        prev_score = aspiration_windows(td, &mut pv, prev_score, board, tt, depth);
+            assert_eq!(0, td.ply);
+            assert_eq!(0, td.accumulators.top);
 
-        assert_eq!(0, td.accumulators.top);
+            let score = aspiration_windows(td, &mut pv, prev_score, board, tt, depth);
 
-        if td.halt() {
-            break;
+            assert_eq!(0, td.accumulators.top);
+
+            if td.halt() {
+                break 'deepening;
+            }
+
+            // Only update lines if the search wasn't aborted
+            let line = PvLine { score, pv };
+            if pv_idx < td.pv_lines.len() {
+                td.pv_lines[pv_idx] = line;
+            } else {
+                td.pv_lines.push(line);
+            }
+            // Lines searched this iteration are kept ordered from best to worst, so the first line
+            // always holds the best move
+            td.pv_lines[..=pv_idx].sort_by_key(|line| Reverse(line.score));
+            td.best_move = td.pv_lines[0].first_move();
         }
 
-        // Only update best move if the search wasn't aborted
-        td.best_move = pv.line[0];
+        let best = &td.pv_lines[0];
+        if prev_move.is_some_and(|m| m == best.first_move()) {
+            move_stability += 1;
+        } else {
+            move_stability = 0;
+        }
+        // A score that drops or climbs away from where previous iterations put it resets its stability
+        if average_score.is_some_and(|average: i32| (best.score - average).abs() <= SCORE_STABILITY_MARGIN) {
+            score_stability += 1;
+        } else {
+            score_stability = 0;
+        }
+        prev_move = Some(best.first_move());
+        average_score = Some(average_score.map_or(best.score, |average| (average + best.score) / 2));
 
-        if td.soft_stop(depth, prev_score) {
+        if td.soft_stop(depth, best.score, move_stability, score_stability) {
             td.set_halt(true);
             break;
         }
 
         if print_uci {
-            td.print_search_stats(prev_score, &pv, tt, depth);
+            td.print_search_stats(tt, depth);
         }
 
         depth += 1;
     }
 
     if print_uci {
-        td.print_search_stats(prev_score, &pv, tt, depth);
+        td.print_search_stats(tt, depth);
     }
 
-    assert_ne!(td.best_move, Move::NULL);
+    // A search stopped before its first iteration finished still has to report a move
+    if td.best_move.is_none() {
+        td.best_move = board.legal_moves().iter().find(|&m| !td.is_excluded_root_move(m));
+    }
 }
 
 /// Aspiration windows place a bound around the likely range the score for a search will fall
@@ -336,6 +377,11 @@ fn negamax<const IS_PV: bool>(
             continue;
         }
 
+        // Moves that already head a better MultiPV line this iteration aren't searched again
+        if is_root && td.is_excluded_root_move(m) {
+            continue;
+        }
+
         if !board.is_legal(m) {
             continue;
         }
@@ -381,7 +427,7 @@ fn negamax<const IS_PV: bool>(
         // negative extensions
         let extension = extension::<IS_PV>(entry, alpha, beta, m, depth, board, td, tt, cut_node);
 
-        td.accumulators.push(m, board.piece_at(m.from()), board.piece_at(m.to()));
+        td.accumulators.push(m, board.piece_at(m.from()), board.capture(m));
 
         if is_quiet {
             quiets_tried.push(m);
@@ -528,8 +574,9 @@ fn negamax<const IS_PV: bool>(
         td.history.corr_hist.update_table(board.stm, board.pawn_hash, depth, best_score - corrected_eval);
     }
 
-    // Don't save to TT while in a singular extension verification search
-    if !singular_search {
+    // Don't save to TT while in a singular extension verification search, or when MultiPV or
+    // searchmoves have excluded some of the root moves
+    if !(singular_search || is_root && (td.pv_idx > 0 || !td.search_moves.is_empty())) {
         tt.store(board.zobrist_hash, best_move, depth, entry_flag, best_score, td.ply, tt_pv, raw_eval);
     }
 
@@ -682,7 +729,7 @@ pub(super) fn quiescence<const IS_PV: bool>(
         let new_b = board.make_move(m);
         tt.prefetch(board.hash_after(Some(m)));
 
-        td.accumulators.push(m, board.piece_at(m.from()), board.piece_at(m.to()));
+        td.accumulators.push(m, board.piece_at(m.from()), board.capture(m));
         td.hash_history.push(new_b.zobrist_hash);
         td.stack[td.ply].played_move = Some(m);
         td.stack[td.ply].moved_piece = board.piece_at(m.from());
@@ -37,6 +37,11 @@ impl Board {
     /// trading pieces, and false if they would come out behind in piece value
     // Based off implementation in Stormphrax, which got its implementation from Weiss
     pub fn see(&self, m: Move, threshold: i32) -> bool {
+        // Castling can't lose material, as the king never lands on an attacked square
+        if m.is_castle() {
+            return threshold <= 0;
+        }
+
         let from = m.from();
         let to = m.to();
 
@@ -108,7 +113,7 @@ pub fn see_test() {
     SEE_POSITIONS.iter().enumerate().for_each(|(test_num, str)| {
         let mut iter = str.split('|');
         let board = Board::from_fen(iter.next().unwrap());
-        let m = Move::from_san(iter.next().unwrap().trim(), &board);
+        let m = Move::from_uci(iter.next().unwrap().trim(), &board);
         let threshold = iter.next().unwrap().trim().parse::<i32>().unwrap();
         assert!(board.see(m, threshold), "{test_num} failed");
     });
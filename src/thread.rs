@@ -1,9 +1,13 @@
 use std::{
-    io,
+    collections::VecDeque,
     process::exit,
-    sync::atomic::{AtomicBool, AtomicU64, Ordering},
-    thread,
-    time::Instant,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, Scope},
+    time::{Duration, Instant},
 };
 
 use crate::{
@@ -12,13 +16,13 @@ use crate::{
     eval::accumulator::{Accumulator, AccumulatorStack},
     history_table::HistoryTable,
     search::{
-        game_time::Clock,
+        game_time::{fixed_move_time, TimeOptions},
         lmr_table::LmrTable,
         search::{start_search, CHECKMATE, MAX_SEARCH_DEPTH, NEAR_CHECKMATE},
-        SearchStack, SearchType, PV,
+        PvLine, SearchLimits, SearchStack,
     },
     transposition::TranspositionTable,
-    uci::parse_time,
+    uci::{find_move, GoCommand, UciCommand, UciError},
 };
 
 #[derive(Clone)]
@@ -27,6 +31,14 @@ pub struct ThreadData<'a> {
     /// Max depth reached by search (include qsearch)
     pub sel_depth: i32,
     pub best_move: Option<Move>,
+    /// Number of best root moves to report
+    pub multi_pv: usize,
+    /// Index of the MultiPV line currently being searched
+    pub pv_idx: usize,
+    /// Completed lines for this search, ordered from best to worst
+    pub pv_lines: Vec<PvLine>,
+    /// Root moves the search is restricted to. All moves are searched if empty.
+    pub search_moves: Vec<Move>,
 
     pub nodes_table: [[u64; 64]; 64],
     pub nodes: AtomicCounter<'a>,
@@ -37,14 +49,18 @@ pub struct ThreadData<'a> {
 
     pub search_start: Instant,
     thread_id: usize,
-    pub search_type: SearchType,
+    pub limits: SearchLimits,
+    /// Limits to switch to once the GUI reports a ponderhit
+    pub ponder_limits: Option<SearchLimits>,
     halt: &'a AtomicBool,
+    pondering: &'a AtomicBool,
     pub lmr: &'a LmrTable,
 }
 
 impl<'a> ThreadData<'a> {
     pub(crate) fn new(
         halt: &'a AtomicBool,
+        pondering: &'a AtomicBool,
         hash_history: Vec<u64>,
         thread_idx: usize,
         lmr: &'a LmrTable,
@@ -55,12 +71,18 @@ impl<'a> ThreadData<'a> {
             stack: SearchStack::default(),
             sel_depth: 0,
             best_move: Move::NULL,
+            multi_pv: 1,
+            pv_idx: 0,
+            pv_lines: Vec::new(),
+            search_moves: Vec::new(),
             nodes: AtomicCounter::new(global_nodes),
             history: HistoryTable::default(),
             nodes_table: [[0; 64]; 64],
             accumulators: AccumulatorStack::new(Accumulator::default()),
             halt,
-            search_type: SearchType::default(),
+            pondering,
+            limits: SearchLimits::default(),
+            ponder_limits: None,
             hash_history,
             thread_id: thread_idx,
             lmr,
@@ -76,72 +98,105 @@ impl<'a> ThreadData<'a> {
         self.halt.load(Ordering::Relaxed)
     }
 
-    pub(super) fn node_tm_stop(&mut self, game_time: Clock, depth: i32) -> bool {
-        let Some(m) = self.best_move else { return false };
-        let frac = self.nodes_table[m.from()][m.to()] as f64 / self.nodes.global_count() as f64;
-        let time_scale = if depth > 9 { (1.44 - frac) * 1.62 } else { 1.28 };
-        if self.search_start.elapsed().as_millis() as f64 >= game_time.rec_time.as_millis() as f64 * time_scale {
-            return true;
+    /// Pondering searches run without limits. Once the GUI reports that the opponent played the
+    /// expected move, the real limits take over and the clock starts running from that moment.
+    fn check_ponderhit(&mut self) {
+        if let Some(limits) = self.ponder_limits {
+            if !self.pondering.load(Ordering::Relaxed) {
+                self.limits = limits;
+                self.ponder_limits = None;
+                self.search_start = Instant::now();
+            }
         }
-        false
     }
 
-    pub(super) fn soft_stop(&mut self, depth: i32, prev_score: i32) -> bool {
-        match self.search_type {
-            SearchType::Depth(d) => depth >= d,
-            SearchType::Time(time) => {
-                self.main_thread() && self.node_tm_stop(time, depth) || time.soft_termination(self.search_start)
-            }
-            SearchType::Nodes(n) => self.nodes.global_count() >= n,
-            SearchType::Infinite => self.halt.load(Ordering::Relaxed),
-            SearchType::Mate(d) => {
-                let dist = if prev_score.is_positive() {
-                    (CHECKMATE - prev_score + 1) / 2
-                } else {
-                    -(CHECKMATE + prev_score) / 2
-                };
-                dist.abs() <= d.abs() || depth > MAX_SEARCH_DEPTH
+    /// Checked between iterations of iterative deepening. Time limits are only checked by the main
+    /// thread, which halts the helper threads once it decides to stop.
+    pub(super) fn soft_stop(
+        &mut self,
+        depth: i32,
+        prev_score: i32,
+        move_stability: usize,
+        score_stability: usize,
+    ) -> bool {
+        self.check_ponderhit();
+        let limits = self.limits;
+        if self.halt() {
+            return true;
+        }
+        if limits.depth.is_some_and(|d| depth >= d) || limits.nodes.is_some_and(|n| self.nodes.global_count() >= n) {
+            return true;
+        }
+        if let Some(d) = limits.mate {
+            let dist =
+                if prev_score.is_positive() { (CHECKMATE - prev_score + 1) / 2 } else { -(CHECKMATE + prev_score) / 2 };
+            if dist.abs() <= d.abs() || depth > MAX_SEARCH_DEPTH {
+                return true;
             }
         }
+        if !self.main_thread() {
+            return false;
+        }
+        let node_frac = self
+            .best_move
+            .map_or(0., |m| self.nodes_table[m.from()][m.to()] as f64 / self.nodes.global_count().max(1) as f64);
+        limits.movetime.is_some_and(|time| self.search_start.elapsed() >= time)
+            || limits.clock.is_some_and(|clock| {
+                self.search_start.elapsed() >= clock.soft_limit(depth, node_frac, move_stability, score_stability)
+            })
     }
 
+    /// Checked by the main thread at every node
     pub(super) fn hard_stop(&mut self) -> bool {
-        match self.search_type {
-            SearchType::Mate(_) | SearchType::Depth(_) | SearchType::Infinite => self.halt.load(Ordering::Relaxed),
-            SearchType::Time(time) => self.nodes.check_time() && time.hard_termination(self.search_start),
-            SearchType::Nodes(n) => self.nodes.global_count() >= n,
+        self.check_ponderhit();
+        let limits = self.limits;
+        if self.halt() || limits.nodes.is_some_and(|n| self.nodes.global_count() >= n) {
+            return true;
         }
+        self.nodes.check_time()
+            && (limits.movetime.is_some_and(|time| self.search_start.elapsed() >= time)
+                || limits.clock.is_some_and(|clock| clock.hard_termination(self.search_start)))
     }
 
-    pub(super) fn print_search_stats(&self, eval: i32, pv: &PV, tt: &TranspositionTable, depth: i32) {
+    /// Root moves outside of `go searchmoves`, as well as moves heading a line already searched
+    /// during this iteration, are excluded from the search
+    pub(super) fn is_excluded_root_move(&self, m: Move) -> bool {
+        (!self.search_moves.is_empty() && !self.search_moves.contains(&m))
+            || self.pv_lines[..self.pv_idx].iter().any(|line| line.first_move() == Some(m))
+    }
+
+    pub(super) fn print_search_stats(&self, tt: &TranspositionTable, depth: i32) {
         let nodes = self.nodes.global_count();
-        print!(
-            "info time {} depth {} seldepth {} nodes {} nps {} score ",
-            self.search_start.elapsed().as_millis(),
-            depth,
-            self.sel_depth,
-            nodes,
-            (nodes as f64 / self.search_start.elapsed().as_secs_f64()) as i64,
-        );
-
-        let score = eval;
-
-        if score.abs() >= NEAR_CHECKMATE {
-            if score.is_positive() {
-                print!("mate {}", (CHECKMATE - score + 1) / 2);
+        for (idx, line) in self.pv_lines.iter().enumerate() {
+            print!(
+                "info time {} depth {} seldepth {} multipv {} nodes {} nps {} score ",
+                self.search_start.elapsed().as_millis(),
+                depth,
+                self.sel_depth,
+                idx + 1,
+                nodes,
+                (nodes as f64 / self.search_start.elapsed().as_secs_f64()) as i64,
+            );
+
+            let score = line.score;
+
+            if score.abs() >= NEAR_CHECKMATE {
+                if score.is_positive() {
+                    print!("mate {}", (CHECKMATE - score + 1) / 2);
+                } else {
+                    print!("mate {}", (-(CHECKMATE + score) / 2));
+                }
             } else {
-                print!("mate {}", (-(CHECKMATE + score) / 2));
+                print!("cp {score}");
             }
-        } else {
-            print!("cp {score}");
-        }
 
-        print!(" hashfull {} pv ", tt.permille_usage());
+            print!(" hashfull {} pv ", tt.permille_usage());
 
-        for m in pv.line.iter().take(pv.line.len()) {
-            print!("{} ", m.unwrap().to_san());
+            for m in &line.pv.line {
+                print!("{} ", m.unwrap().to_uci());
+            }
+            println!();
         }
-        println!();
     }
 
     pub(super) fn is_repetition(&self, board: &Board) -> bool {
@@ -159,110 +214,252 @@ impl<'a> ThreadData<'a> {
         false
     }
 
+    /// Move the engine expects the opponent to reply with. Falls back on the transposition table
+    /// when the principal variation was cut short.
+    fn ponder_move(&self, board: &Board, tt: &TranspositionTable) -> Option<Move> {
+        if let Some(&m) = self.pv_lines.first().and_then(|line| line.pv.line.get(1)) {
+            return m;
+        }
+
+        let new_b = board.make_move(self.best_move?);
+        let m = tt.get(new_b.zobrist_hash, 0)?.best_move();
+        if new_b.is_pseudo_legal(m) && new_b.is_legal(m?) {
+            m
+        } else {
+            None
+        }
+    }
+
     pub fn main_thread(&self) -> bool {
         self.thread_id == 0
     }
 }
 
-pub struct ThreadPool<'a> {
-    pub threads: Vec<ThreadData<'a>>,
+/// Everything a worker needs to know to start searching a position
+struct SearchRequest {
+    board: Board,
+    tt: Arc<TranspositionTable>,
+    limits: SearchLimits,
+    ponder_limits: Option<SearchLimits>,
+    search_moves: Vec<Move>,
+    hash_history: Vec<u64>,
+    multi_pv: usize,
+    /// Whether the main thread prints info lines and its best move
+    print_uci: bool,
+}
+
+enum WorkerRequest {
+    Search(Box<SearchRequest>),
+    /// Forget everything learned in previous games, or with a previous net
+    Reset,
+}
+
+impl ThreadData<'_> {
+    fn search(&mut self, request: SearchRequest) {
+        let SearchRequest { board, tt, limits, ponder_limits, search_moves, hash_history, multi_pv, print_uci } =
+            request;
+        self.limits = limits;
+        self.ponder_limits = ponder_limits;
+        self.search_moves = search_moves;
+        self.hash_history = hash_history;
+        self.multi_pv = multi_pv;
+        self.nodes.clear_local();
+
+        start_search(self, self.main_thread() && print_uci, board, &tt);
+        self.nodes.flush();
+        self.set_halt(true);
+        if self.main_thread() && print_uci {
+            // A best move may not be sent while pondering, even if the search finished
+            while self.pondering.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            // Positions without any legal moves are reported with a null move
+            match (self.best_move, self.ponder_move(&board, &tt)) {
+                (Some(m), Some(p)) => println!("bestmove {} ponder {}", m.to_uci(), p.to_uci()),
+                (Some(m), None) => println!("bestmove {}", m.to_uci()),
+                (None, _) => println!("bestmove 0000"),
+            }
+        }
+    }
+}
+
+/// Search threads live for as long as the engine does. Between searches they wait on their
+/// channel, so starting a search only costs a message and their tables carry over between moves.
+pub struct ThreadPool<'scope, 'a> {
+    scope: &'scope Scope<'scope, 'a>,
+    workers: Vec<Sender<WorkerRequest>>,
+    /// Each worker reports here once it has finished a search
+    finished_sender: Sender<()>,
+    finished: Receiver<()>,
+    pub multi_pv: usize,
+    pub time_options: TimeOptions,
+    /// Whether searches print info lines and a best move, as the GUI expects
+    pub print_uci: bool,
+    halt: &'a AtomicBool,
+    pondering: &'a AtomicBool,
+    lmr: &'a LmrTable,
+    global_nodes: &'a AtomicU64,
 }
 
-impl<'a> ThreadPool<'a> {
-    pub fn new(halt: &'a AtomicBool, hash_history: Vec<u64>, lmr: &'a LmrTable, global_nodes: &'a AtomicU64) -> Self {
-        Self { threads: vec![ThreadData::new(halt, hash_history, 0, lmr, global_nodes)] }
+impl<'scope, 'a> ThreadPool<'scope, 'a> {
+    pub fn new(
+        scope: &'scope Scope<'scope, 'a>,
+        halt: &'a AtomicBool,
+        pondering: &'a AtomicBool,
+        lmr: &'a LmrTable,
+        global_nodes: &'a AtomicU64,
+    ) -> Self {
+        let (finished_sender, finished) = mpsc::channel();
+        let mut pool = Self {
+            scope,
+            workers: Vec::new(),
+            finished_sender,
+            finished,
+            multi_pv: 1,
+            time_options: TimeOptions::default(),
+            print_uci: true,
+            halt,
+            pondering,
+            lmr,
+            global_nodes,
+        };
+        pool.add_workers(1);
+        pool
+    }
+
+    fn spawn_worker(&mut self) {
+        let (sender, requests) = mpsc::channel();
+        let thread_idx = self.workers.len();
+        let mut td =
+            Box::new(ThreadData::new(self.halt, self.pondering, Vec::new(), thread_idx, self.lmr, self.global_nodes));
+        let finished = self.finished_sender.clone();
+        self.scope.spawn(move || {
+            // The worker exits once the pool drops its sender
+            for request in requests {
+                match request {
+                    WorkerRequest::Search(request) => {
+                        td.search(*request);
+                        finished.send(()).unwrap();
+                    }
+                    WorkerRequest::Reset => {
+                        td.history = HistoryTable::default();
+                        // Cached accumulators may have been built by a net that has since been replaced
+                        td.accumulators = AccumulatorStack::new(Accumulator::default());
+                    }
+                }
+            }
+        });
+        self.workers.push(sender);
     }
 
-    /// This thread creates a number of workers equal to threads - 1. If 4 threads are requested,
-    /// the main thread counts as one and then the remaining three are placed in the worker queue.
+    /// Resizes the pool to the requested number of threads, the main thread included. Existing
+    /// workers are kept, and workers that are no longer needed shut down.
     pub fn add_workers(&mut self, threads: usize) {
-        // Might as well use whatever history values the main thread has if any.
-        self.threads = vec![self.threads[0].clone(); threads];
-        for (idx, t) in self.threads.iter_mut().enumerate() {
-            t.thread_id = idx;
+        self.workers.truncate(threads.max(1));
+        while self.workers.len() < threads {
+            self.spawn_worker();
         }
     }
 
+    /// Nodes searched by every thread during the last search
+    pub fn nodes(&self) -> u64 {
+        self.global_nodes.load(Ordering::Relaxed)
+    }
+
+    /// Runs `f` with a pool of fresh threads sharing this pool's flags and node count, so searches
+    /// made through it leave the tables of this pool's threads untouched
+    pub fn with_separate_pool<R>(&self, f: impl for<'s> FnOnce(&mut ThreadPool<'s, 'a>) -> R) -> R {
+        let (halt, pondering, lmr, global_nodes) = (self.halt, self.pondering, self.lmr, self.global_nodes);
+        thread::scope(|s| f(&mut ThreadPool::new(s, halt, pondering, lmr, global_nodes)))
+    }
+
     pub fn reset(&mut self) {
-        for t in &mut self.threads {
-            t.history = HistoryTable::default();
-            t.nodes.reset();
+        for worker in &self.workers {
+            worker.send(WorkerRequest::Reset).unwrap();
         }
     }
 
     pub fn handle_go(
         &mut self,
-        buffer: &[&str],
+        go: &GoCommand,
         board: &Board,
-        halt: &AtomicBool,
-        msg: &mut Option<String>,
+        commands: &Receiver<String>,
+        pending: &mut VecDeque<String>,
         hash_history: &[u64],
-        tt: &TranspositionTable,
+        tt: &Arc<TranspositionTable>,
     ) {
-        halt.store(false, Ordering::Relaxed);
-        for t in &mut self.threads {
-            hash_history.clone_into(&mut t.hash_history);
-            t.nodes.reset();
+        for e in &go.ignored {
+            println!("info string warning: {e}, ignoring it");
         }
-
-        if buffer.contains(&"depth") {
-            let mut iter = buffer.iter().skip(2);
-            let depth = iter.next().unwrap().parse::<i32>().unwrap();
-            for t in &mut self.threads {
-                t.search_type = SearchType::Depth(depth);
-            }
-        } else if buffer.contains(&"nodes") {
-            let mut iter = buffer.iter().skip(2);
-            let nodes = iter.next().unwrap().parse::<u64>().unwrap();
-            for t in &mut self.threads {
-                t.search_type = SearchType::Nodes(nodes);
-            }
-        } else if buffer.contains(&"wtime") {
-            let mut clock = parse_time(buffer);
+        // A go always has to be answered with a bestmove, so moves that can't be played are left
+        // out rather than failing the command. Without any moves left every move gets searched.
+        let search_moves = go
+            .search_moves
+            .iter()
+            .filter_map(|str| {
+                let m = find_move(str, board);
+                if m.is_none() {
+                    println!("info string warning: {}, ignoring it", UciError::IllegalMove(str.clone()));
+                }
+                m
+            })
+            .collect::<Vec<_>>();
+
+        // Helper threads share the main thread's limits so that they stop on depth, nodes and mate too
+        let mut limits = go.limits;
+        limits.movetime = limits.movetime.map(|time| fixed_move_time(time, self.time_options));
+        if let Some(clock) = &mut limits.clock {
+            clock.options = self.time_options;
             clock.recommended_time(board.stm);
+        }
 
-            for t in &mut self.threads {
-                t.search_type = SearchType::Infinite;
-            }
-            self.threads[0].search_type = SearchType::Time(clock);
-        } else if buffer.contains(&"mate") {
-            let mut iter = buffer.iter().skip(2);
-            let ply = iter.next().unwrap().parse::<i32>().unwrap();
-            for t in &mut self.threads {
-                t.search_type = SearchType::Mate(ply);
-            }
-        } else {
-            for t in &mut self.threads {
-                t.search_type = SearchType::Infinite;
-            }
+        self.halt.store(false, Ordering::Relaxed);
+        self.global_nodes.store(0, Ordering::Relaxed);
+
+        // Search on the opponent's time without limits until the GUI sends ponderhit or stop
+        let ponder = go.ponder;
+        self.pondering.store(ponder, Ordering::Relaxed);
+        let (limits, ponder_limits) = if ponder { (SearchLimits::default(), Some(limits)) } else { (limits, None) };
+
+        for (idx, worker) in self.workers.iter().enumerate() {
+            let request = SearchRequest {
+                board: *board,
+                tt: Arc::clone(tt),
+                limits,
+                ponder_limits,
+                search_moves: search_moves.clone(),
+                hash_history: hash_history.to_vec(),
+                // Helper threads only need to find the best move
+                multi_pv: if idx == 0 { self.multi_pv } else { 1 },
+                print_uci: self.print_uci,
+            };
+            worker.send(WorkerRequest::Search(Box::new(request))).unwrap();
         }
 
-        thread::scope(|s| {
-            for t in &mut self.threads {
-                s.spawn(|| {
-                    start_search(t, t.main_thread(), *board, tt);
-                    halt.store(true, Ordering::Relaxed);
-                    if t.main_thread() {
-                        println!("bestmove {}", t.best_move.unwrap().to_san());
-                    }
-                });
+        // Commands that can't be acted on until the search is over are queued for the main loop
+        let mut running = self.workers.len();
+        while running > 0 {
+            if self.finished.try_recv().is_ok() {
+                running -= 1;
+                continue;
             }
-
-            let mut s = String::new();
-            let len_read = io::stdin().read_line(&mut s).unwrap();
-            if len_read == 0 {
+            let line = match commands.recv_timeout(Duration::from_millis(1)) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => continue,
                 // Stdin closed, exit for openbench
-                exit(0);
-            }
-            match s.as_str().trim() {
-                "isready" => println!("readyok"),
-                "quit" => exit(0),
-                "stop" => halt.store(true, Ordering::Relaxed),
-                _ => {
-                    *msg = Some(s);
+                Err(RecvTimeoutError::Disconnected) => exit(0),
+            };
+            match line.parse() {
+                Ok(UciCommand::IsReady) => println!("readyok"),
+                Ok(UciCommand::Quit) => exit(0),
+                Ok(UciCommand::Stop) => {
+                    self.pondering.store(false, Ordering::Relaxed);
+                    self.halt.store(true, Ordering::Relaxed);
                 }
+                Ok(UciCommand::PonderHit) => self.pondering.store(false, Ordering::Relaxed),
+                _ => pending.push_back(line),
             }
-        });
+        }
         tt.age_up();
     }
 }
@@ -299,9 +496,22 @@ impl<'a> AtomicCounter<'a> {
     }
 
     pub(crate) fn reset(&mut self) {
+        self.clear_local();
+        self.global_nodes.store(0, Ordering::Relaxed);
+    }
+
+    /// Adds the nodes not yet counted in the shared count, so it is exact once every thread has
+    /// finished searching
+    pub(crate) fn flush(&mut self) {
+        self.local_nodes += self.batch;
+        self.global_nodes.fetch_add(self.batch, Ordering::Relaxed);
+        self.batch = 0;
+    }
+
+    /// Resets this thread's count without touching the count shared by all threads
+    pub(crate) fn clear_local(&mut self) {
         self.batch = 0;
         self.local_nodes = 0;
-        self.global_nodes.store(0, Ordering::Relaxed);
     }
 
     pub(crate) const fn check_time(&self) -> bool {
@@ -0,0 +1,152 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Time an engine gets to answer `uci` and `isready`
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Scores are reported as centipawns, with mates converted to scores just past this
+pub const MATE_SCORE: i32 = 100_000;
+
+/// What an engine sent back once it finished searching
+#[derive(Debug, PartialEq, Eq)]
+pub struct Reply {
+    /// Best move in UCI notation
+    pub best_move: String,
+    /// Last score reported before the best move, from the engine's point of view
+    pub score: Option<i32>,
+    pub elapsed: Duration,
+}
+
+/// A UCI engine running as a child process. Its output is read on a separate thread so that waiting
+/// for a reply can time out.
+pub struct Engine {
+    pub name: String,
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+}
+
+impl Engine {
+    /// Starts the engine and waits for it to finish its UCI handshake, then sets its options
+    pub fn start(path: &str, options: &[(String, String)]) -> Result<Self, String> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("couldn't start {path}: {e}"))?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut engine = Self { name: path.to_string(), child, stdin, lines };
+        engine.send("uci")?;
+        engine.wait_for("uciok", HANDSHAKE_TIMEOUT)?;
+        for (name, value) in options {
+            engine.send(&format!("setoption name {name} value {value}"))?;
+        }
+        engine.is_ready()?;
+        Ok(engine)
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), String> {
+        writeln!(self.stdin, "{command}").map_err(|e| format!("{}: couldn't send '{command}': {e}", self.name))
+    }
+
+    /// Reads lines until one starting with `token`, which is returned
+    fn wait_for(&mut self, token: &str, timeout: Duration) -> Result<String, String> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.lines.recv_timeout(remaining) {
+                Ok(line) if line.split_whitespace().next() == Some(token) => return Ok(line),
+                Ok(_) => (),
+                Err(RecvTimeoutError::Timeout) => return Err(format!("{}: timed out waiting for {token}", self.name)),
+                Err(RecvTimeoutError::Disconnected) => return Err(format!("{}: exited", self.name)),
+            }
+        }
+    }
+
+    fn is_ready(&mut self) -> Result<(), String> {
+        self.send("isready")?;
+        self.wait_for("readyok", HANDSHAKE_TIMEOUT).map(|_| ())
+    }
+
+    pub fn new_game(&mut self) -> Result<(), String> {
+        self.send("ucinewgame")?;
+        self.is_ready()
+    }
+
+    /// Sends the position and go command, then waits up to `timeout` for the best move. Running
+    /// out of time isn't an error, so `None` is returned instead.
+    pub fn go(&mut self, position: &str, go: &str, timeout: Duration) -> Result<Option<Reply>, String> {
+        self.send(position)?;
+        let start = Instant::now();
+        self.send(go)?;
+
+        let mut score = None;
+        loop {
+            let remaining = timeout.saturating_sub(start.elapsed());
+            let line = match self.lines.recv_timeout(remaining) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => return Ok(None),
+                Err(RecvTimeoutError::Disconnected) => return Err(format!("{}: exited", self.name)),
+            };
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("info") => score = parse_score(&line).or(score),
+                Some("bestmove") => {
+                    let best_move = tokens.next().unwrap_or_default().to_string();
+                    return Ok(Some(Reply { best_move, score, elapsed: start.elapsed() }));
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        // Give the engine a moment to exit on its own before killing it
+        let deadline = Instant::now() + Duration::from_millis(500);
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Score of an info line in centipawns, where a mate in N moves is worth `MATE_SCORE - N`
+pub fn parse_score(line: &str) -> Option<i32> {
+    let tokens = line.split_whitespace().collect::<Vec<_>>();
+    let idx = tokens.iter().position(|&t| t == "score")?;
+    // Bounds are only reported while a search is unsure of the score
+    if tokens.get(idx + 3).is_some_and(|&t| t == "lowerbound" || t == "upperbound") {
+        return None;
+    }
+    let value = tokens.get(idx + 2)?.parse::<i32>().ok()?;
+    match *tokens.get(idx + 1)? {
+        "cp" => Some(value),
+        "mate" if value > 0 => Some(MATE_SCORE - value),
+        "mate" => Some(-MATE_SCORE - value),
+        _ => None,
+    }
+}
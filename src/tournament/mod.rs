@@ -0,0 +1,507 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        mpsc::{self, Sender},
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    board::Board,
+    epd::Epd,
+    pgn::{Game, PgnMove},
+    types::pieces::Color,
+    uci::find_move,
+};
+
+use self::{
+    engine::Engine,
+    sprt::{Sprt, Stats},
+};
+
+mod engine;
+mod sprt;
+
+/// Extra time an engine may use beyond its clock before it forfeits, to make up for the delay of
+/// talking through pipes
+const TIME_MARGIN: Duration = Duration::from_millis(100);
+/// Engines that don't answer a `go nodes` search within this long are assumed to be stuck
+const NODES_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Games are resigned for the losing side once both engines agree on a score this large...
+const RESIGN_SCORE: i32 = 1000;
+/// ...for this many plies in a row
+const RESIGN_PLIES: usize = 6;
+/// Games are drawn once both engines agree the score is within this many centipawns of zero...
+const DRAW_SCORE: i32 = 10;
+/// ...for this many plies in a row
+const DRAW_PLIES: usize = 16;
+/// ...but only once this many plies have been played
+const DRAW_MIN_PLY: usize = 80;
+
+const USAGE: &str = "match <engine1> <engine2> --openings <file> (--tc <base+inc> | --nodes N) [--games N] \
+    [--concurrency N] [--option Name=Value]... [--sprt elo0 elo1] [--pgn <file>]";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeControl {
+    /// Base time for the game plus an increment for every move
+    Clock { base: Duration, inc: Duration },
+    /// Fixed number of nodes per move
+    Nodes(u64),
+}
+
+impl FromStr for TimeControl {
+    type Err = String;
+
+    /// Parses a clock given in seconds, e.g. `8+0.08` or `60`
+    fn from_str(tc: &str) -> Result<Self, Self::Err> {
+        let (base, inc) = tc.split_once('+').unwrap_or((tc, "0"));
+        let seconds = |s: &str| {
+            s.parse::<f64>()
+                .ok()
+                .filter(|&s| s >= 0.)
+                .map(Duration::from_secs_f64)
+                .ok_or_else(|| format!("invalid time control '{tc}'"))
+        };
+        Ok(Self::Clock { base: seconds(base)?, inc: seconds(inc)? })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchConfig {
+    pub engines: [String; 2],
+    pub openings: String,
+    pub control: TimeControl,
+    /// Games to play, two per opening unless given
+    pub games: Option<u32>,
+    /// Games played at the same time
+    pub concurrency: usize,
+    /// UCI options set on both engines
+    pub options: Vec<(String, String)>,
+    pub sprt: Option<Sprt>,
+    /// File finished games are written to
+    pub pgn: Option<String>,
+}
+
+impl MatchConfig {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let usage = || format!("usage: {USAGE}");
+        let mut positional = Vec::new();
+        let (mut openings, mut control, mut games, mut concurrency) = (None, None, None, 1);
+        let (mut options, mut sprt, mut pgn) = (Vec::new(), None, None);
+
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--openings" => openings = Some(flag_value(arg, iter.next())?),
+                "--tc" => control = Some(flag_value::<String>(arg, iter.next())?.parse()?),
+                "--nodes" => control = Some(TimeControl::Nodes(flag_value(arg, iter.next())?)),
+                "--games" => games = Some(flag_value(arg, iter.next())?),
+                "--concurrency" => concurrency = flag_value(arg, iter.next())?,
+                "--option" => {
+                    let option = flag_value::<String>(arg, iter.next())?;
+                    let (name, value) =
+                        option.split_once('=').ok_or_else(|| format!("option '{option}' should be Name=Value"))?;
+                    options.push((name.to_string(), value.to_string()));
+                }
+                "--sprt" => sprt = Some(Sprt::new(flag_value(arg, iter.next())?, flag_value(arg, iter.next())?)),
+                "--pgn" => pgn = Some(flag_value(arg, iter.next())?),
+                flag if flag.starts_with("--") => return Err(format!("unknown flag {flag}\n{}", usage())),
+                _ => positional.push(arg.clone()),
+            }
+        }
+
+        let [first, second] = <[String; 2]>::try_from(positional).map_err(|_| usage())?;
+        Ok(Self {
+            engines: [first, second],
+            openings: openings.ok_or_else(usage)?,
+            control: control.ok_or_else(usage)?,
+            games,
+            concurrency: concurrency.max(1),
+            options,
+            sprt,
+            pgn,
+        })
+    }
+}
+
+/// Parses the value following a flag, failing if it is missing or malformed
+pub fn flag_value<T: FromStr>(flag: &str, value: Option<&String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("missing value for {flag}"))?;
+    value.parse().map_err(|_| format!("invalid value '{value}' for {flag}"))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Outcome {
+    WhiteWins,
+    BlackWins,
+    Draw,
+}
+
+impl Outcome {
+    const fn result(self) -> &'static str {
+        match self {
+            Self::WhiteWins => "1-0",
+            Self::BlackWins => "0-1",
+            Self::Draw => "1/2-1/2",
+        }
+    }
+
+    const fn win_for(color: Color) -> Self {
+        match color {
+            Color::White => Self::WhiteWins,
+            Color::Black => Self::BlackWins,
+        }
+    }
+}
+
+/// A finished game and who played it
+struct Finished {
+    round: u32,
+    /// Index of the engine that had white
+    white: usize,
+    outcome: Outcome,
+    /// Why the game ended, e.g. "White mates"
+    reason: String,
+    game: Game,
+}
+
+/// Plays a match between two engines, printing the score after every game. With an SPRT the match
+/// stops early once either hypothesis is accepted.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let config = MatchConfig::parse(args)?;
+    let text = fs::read_to_string(&config.openings).map_err(|e| format!("couldn't read {}: {e}", config.openings))?;
+    let openings = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(idx, line)| line.parse::<Epd>().map(|epd| epd.board).map_err(|e| format!("line {}: {e}", idx + 1)))
+        .collect::<Result<Vec<_>, _>>()?;
+    if openings.is_empty() {
+        return Err(format!("{} has no openings", config.openings));
+    }
+    let games = config.games.unwrap_or(2 * openings.len() as u32);
+    let mut pgn = match &config.pgn {
+        Some(path) => Some(File::create(path).map_err(|e| format!("couldn't create {path}: {e}"))?),
+        None => None,
+    };
+
+    let next_round = AtomicU32::new(0);
+    let stop = AtomicBool::new(false);
+    let (sender, results) = mpsc::channel();
+    thread::scope(|s| {
+        for _ in 0..config.concurrency.min(games as usize) {
+            let sender = sender.clone();
+            let (config, openings, next_round, stop) = (&config, &openings, &next_round, &stop);
+            s.spawn(move || play_rounds(config, openings, games, next_round, stop, &sender));
+        }
+        drop(sender);
+
+        let [first, second] = &config.engines;
+        let mut stats = Stats::default();
+        for finished in results {
+            let finished = match finished {
+                Ok(finished) => finished,
+                Err(e) => {
+                    stop.store(true, Ordering::Relaxed);
+                    return Err(e);
+                }
+            };
+            match (finished.outcome, finished.white) {
+                (Outcome::Draw, _) => stats.draws += 1,
+                (Outcome::WhiteWins, 0) | (Outcome::BlackWins, 1) => stats.wins += 1,
+                _ => stats.losses += 1,
+            }
+            let (white, black) = if finished.white == 0 { (first, second) } else { (second, first) };
+            println!(
+                "Finished game {} ({white} vs {black}): {} {{{}}}",
+                finished.round + 1,
+                finished.outcome.result(),
+                finished.reason
+            );
+            println!(
+                "Score of {first} vs {second}: {} - {} - {}  [{:.3}] {}",
+                stats.wins,
+                stats.losses,
+                stats.draws,
+                stats.score(),
+                stats.games()
+            );
+            match stats.elo() {
+                Some((elo, margin)) => println!("Elo difference: {elo:.1} +/- {margin:.1}"),
+                None => println!("Elo difference: unknown"),
+            }
+            if let Some(file) = &mut pgn {
+                writeln!(file, "{}", finished.game).map_err(|e| format!("couldn't write the PGN: {e}"))?;
+            }
+
+            if let Some(sprt) = &config.sprt {
+                let llr = stats.llr(sprt);
+                println!(
+                    "SPRT: llr {llr:.2} ({:.2}, {:.2}) [{:.2}, {:.2}]",
+                    sprt.lower_bound(),
+                    sprt.upper_bound(),
+                    sprt.elo0,
+                    sprt.elo1
+                );
+                if !stop.load(Ordering::Relaxed) && (llr <= sprt.lower_bound() || llr >= sprt.upper_bound()) {
+                    println!("SPRT: H{} accepted", u8::from(llr >= sprt.upper_bound()));
+                    // Games already running are still counted once they finish
+                    stop.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Keeps playing rounds with one pair of engines until every round has been handed out. Each
+/// opening is played twice, with the engines swapping colors.
+fn play_rounds(
+    config: &MatchConfig,
+    openings: &[Board],
+    games: u32,
+    next_round: &AtomicU32,
+    stop: &AtomicBool,
+    sender: &Sender<Result<Finished, String>>,
+) {
+    let start = |idx: usize| Engine::start(&config.engines[idx], &config.options);
+    let mut engines = match start(0).and_then(|first| Ok([first, start(1)?])) {
+        Ok(engines) => engines,
+        Err(e) => {
+            let _ = sender.send(Err(e));
+            return;
+        }
+    };
+
+    while !stop.load(Ordering::Relaxed) {
+        let round = next_round.fetch_add(1, Ordering::Relaxed);
+        if round >= games {
+            break;
+        }
+        let opening = openings[(round / 2) as usize % openings.len()];
+        let white = (round % 2) as usize;
+        let (finished, failed) = play_game(&mut engines, white, opening, config.control, round);
+
+        // An engine that misbehaved may still be thinking, or be gone entirely, so it starts over
+        let mut result = Ok(finished);
+        for idx in failed {
+            match start(idx) {
+                Ok(engine) => engines[idx] = engine,
+                Err(e) => result = Err(e),
+            }
+        }
+        let failed_to_restart = result.is_err();
+        if sender.send(result).is_err() || failed_to_restart {
+            break;
+        }
+    }
+}
+
+/// Plays a single game, returning it along with the engines that had to give up because they
+/// crashed, ran out of time or sent an illegal move
+fn play_game(
+    engines: &mut [Engine; 2],
+    white: usize,
+    opening: Board,
+    control: TimeControl,
+    round: u32,
+) -> (Finished, Vec<usize>) {
+    let mut game = Game::new(opening);
+    game.set_tag("Event", "Titan match");
+    game.set_tag("Round", &(round + 1).to_string());
+    game.set_tag("White", &engines[white].name);
+    game.set_tag("Black", &engines[1 - white].name);
+
+    let mut board = opening;
+    let mut hashes = vec![board.zobrist_hash];
+    // Latest score of each ply, from white's point of view
+    let mut scores = Vec::new();
+    let mut clocks = match control {
+        TimeControl::Clock { base, .. } => [base; 2],
+        TimeControl::Nodes(_) => [Duration::ZERO; 2],
+    };
+    let mut moves = Vec::new();
+    let mut failed = Vec::new();
+
+    let (outcome, reason, termination) = 'game: {
+        for (idx, engine) in engines.iter_mut().enumerate() {
+            if let Err(e) = engine.new_game() {
+                failed.push(idx);
+                let loser = if idx == white { Color::White } else { Color::Black };
+                break 'game (Outcome::win_for(!loser), e, "abandoned");
+            }
+        }
+
+        loop {
+            if let Some((outcome, reason, termination)) = game_over(&board, &hashes, &scores) {
+                break 'game (outcome, reason, termination);
+            }
+
+            let stm = board.stm;
+            let idx = if stm == Color::White { white } else { 1 - white };
+            let (go, timeout) = match control {
+                TimeControl::Clock { inc, .. } => (
+                    format!(
+                        "go wtime {} btime {} winc {} binc {}",
+                        clocks[Color::White].as_millis(),
+                        clocks[Color::Black].as_millis(),
+                        inc.as_millis(),
+                        inc.as_millis()
+                    ),
+                    clocks[stm] + TIME_MARGIN,
+                ),
+                TimeControl::Nodes(nodes) => (format!("go nodes {nodes}"), NODES_TIMEOUT),
+            };
+
+            let position = if moves.is_empty() {
+                format!("position fen {}", opening.to_fen())
+            } else {
+                format!("position fen {} moves {}", opening.to_fen(), moves.join(" "))
+            };
+            let reply = match engines[idx].go(&position, &go, timeout) {
+                Ok(Some(reply)) => reply,
+                Ok(None) => {
+                    failed.push(idx);
+                    break 'game (Outcome::win_for(!stm), format!("{} loses on time", color_name(stm)), "time forfeit");
+                }
+                Err(e) => {
+                    failed.push(idx);
+                    break 'game (Outcome::win_for(!stm), e, "abandoned");
+                }
+            };
+            let Some(m) = find_move(&reply.best_move, &board) else {
+                failed.push(idx);
+                let reason = format!("{} makes an illegal move: {}", color_name(stm), reply.best_move);
+                break 'game (Outcome::win_for(!stm), reason, "rules infraction");
+            };
+
+            if let TimeControl::Clock { inc, .. } = control {
+                clocks[stm] = clocks[stm].saturating_sub(reply.elapsed) + inc;
+            }
+            scores.push(reply.score.map(|score| if stm == Color::White { score } else { -score }));
+            game.mainline.moves.push(PgnMove::new(m));
+            moves.push(m.to_uci());
+            board = board.make_move(m);
+            hashes.push(board.zobrist_hash);
+        }
+    };
+
+    game.set_result(outcome.result());
+    game.set_tag("Termination", termination);
+    match game.mainline.moves.last_mut() {
+        Some(last) => last.add_comment(&reason),
+        None => game.mainline.comment = Some(reason.clone()),
+    }
+    (Finished { round, white, outcome, reason, game }, failed)
+}
+
+/// Result of the game if it is over, either by the rules or because the engines agree on how it
+/// will end. Returns the outcome, a description and the PGN termination.
+fn game_over(board: &Board, hashes: &[u64], scores: &[Option<i32>]) -> Option<(Outcome, String, &'static str)> {
+    if board.legal_moves().len() == 0 {
+        return Some(if board.in_check() {
+            (Outcome::win_for(!board.stm), format!("{} mates", color_name(!board.stm)), "normal")
+        } else {
+            (Outcome::Draw, "Draw by stalemate".to_string(), "normal")
+        });
+    }
+    if board.half_moves >= 100 {
+        return Some((Outcome::Draw, "Draw by fifty moves rule".to_string(), "normal"));
+    }
+    if board.is_draw() {
+        return Some((Outcome::Draw, "Draw by insufficient material".to_string(), "normal"));
+    }
+    if hashes.iter().filter(|&&hash| hash == board.zobrist_hash).count() >= 3 {
+        return Some((Outcome::Draw, "Draw by 3-fold repetition".to_string(), "normal"));
+    }
+
+    // Whether the scores of the last few plies all agree on something
+    let agreed = |plies: usize, agrees: &dyn Fn(i32) -> bool| {
+        scores.len() >= plies && scores[scores.len() - plies..].iter().all(|score| score.is_some_and(agrees))
+    };
+    for (color, sign) in [(Color::White, 1), (Color::Black, -1)] {
+        if agreed(RESIGN_PLIES, &|score| score * sign >= RESIGN_SCORE) {
+            let reason = format!("{} wins by adjudication", color_name(color));
+            return Some((Outcome::win_for(color), reason, "adjudication"));
+        }
+    }
+    if scores.len() >= DRAW_MIN_PLY && agreed(DRAW_PLIES, &|score| score.abs() <= DRAW_SCORE) {
+        return Some((Outcome::Draw, "Draw by adjudication".to_string(), "adjudication"));
+    }
+    None
+}
+
+const fn color_name(color: Color) -> &'static str {
+    match color {
+        Color::White => "White",
+        Color::Black => "Black",
+    }
+}
+
+#[cfg(test)]
+mod tournament_tests {
+    use std::time::Duration;
+
+    use super::{game_over, MatchConfig, Outcome, TimeControl, DRAW_MIN_PLY, RESIGN_SCORE};
+    use crate::{board::Board, fen::STARTING_FEN};
+
+    #[test]
+    fn time_control() {
+        assert_eq!(
+            "8+0.08".parse(),
+            Ok(TimeControl::Clock { base: Duration::from_secs(8), inc: Duration::from_millis(80) })
+        );
+        assert_eq!("60".parse(), Ok(TimeControl::Clock { base: Duration::from_secs(60), inc: Duration::ZERO }));
+        assert!("8+".parse::<TimeControl>().is_err());
+        assert!("-1".parse::<TimeControl>().is_err());
+    }
+
+    #[test]
+    fn parse_config() {
+        let args = "./dev ./base --openings book.epd --nodes 5000 --option Hash=16 --sprt 0 5 --concurrency 4"
+            .split(' ')
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        let config = MatchConfig::parse(&args).unwrap();
+        assert_eq!(config.engines, ["./dev".to_string(), "./base".to_string()]);
+        assert_eq!(config.control, TimeControl::Nodes(5000));
+        assert_eq!(config.options, [("Hash".to_string(), "16".to_string())]);
+        assert_eq!(config.sprt.map(|sprt| (sprt.elo0, sprt.elo1)), Some((0., 5.)));
+        assert_eq!(config.concurrency, 4);
+        assert_eq!(config.games, None);
+
+        // Both engines, an opening book and a time control are required
+        assert!(MatchConfig::parse(&args[1..]).is_err());
+        assert!(MatchConfig::parse(&args[..4]).is_err());
+    }
+
+    #[test]
+    fn adjudication() {
+        let board = Board::from_fen("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3");
+        assert_eq!(game_over(&board, &[], &[]).map(|(outcome, ..)| outcome), Some(Outcome::BlackWins));
+        let board = Board::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1");
+        assert_eq!(game_over(&board, &[], &[]).map(|(outcome, ..)| outcome), Some(Outcome::Draw));
+
+        let board = Board::from_fen(STARTING_FEN);
+        let hash = board.zobrist_hash;
+        assert_eq!(game_over(&board, &[hash, hash], &[]), None);
+        assert!(game_over(&board, &[hash, 1, hash, 2, hash], &[]).is_some());
+
+        // Both engines have to agree for long enough
+        let winning = vec![Some(RESIGN_SCORE); 6];
+        assert_eq!(game_over(&board, &[], &winning).map(|(outcome, ..)| outcome), Some(Outcome::WhiteWins));
+        assert_eq!(game_over(&board, &[], &winning[1..]), None);
+        let mut disputed = winning.clone();
+        disputed[2] = Some(0);
+        assert_eq!(game_over(&board, &[], &disputed), None);
+
+        let drawn = vec![Some(5); DRAW_MIN_PLY];
+        assert_eq!(game_over(&board, &[], &drawn).map(|(outcome, ..)| outcome), Some(Outcome::Draw));
+        assert_eq!(game_over(&board, &[], &drawn[1..]), None);
+    }
+}
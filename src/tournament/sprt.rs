@@ -0,0 +1,122 @@
+/// Bounds for the sequential probability ratio test deciding between two Elo hypotheses
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    /// Chance of accepting `elo1` when `elo0` is true
+    pub alpha: f64,
+    /// Chance of accepting `elo0` when `elo1` is true
+    pub beta: f64,
+}
+
+impl Sprt {
+    pub fn new(elo0: f64, elo1: f64) -> Self {
+        Self { elo0, elo1, alpha: 0.05, beta: 0.05 }
+    }
+
+    /// Log-likelihood ratio below which the test accepts `elo0`
+    pub fn lower_bound(&self) -> f64 {
+        (self.beta / (1. - self.alpha)).ln()
+    }
+
+    /// Log-likelihood ratio above which the test accepts `elo1`
+    pub fn upper_bound(&self) -> f64 {
+        ((1. - self.beta) / self.alpha).ln()
+    }
+}
+
+/// Results from the point of view of the first engine
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+impl Stats {
+    pub const fn games(&self) -> u32 {
+        self.wins + self.losses + self.draws
+    }
+
+    /// Average points per game
+    pub fn score(&self) -> f64 {
+        (f64::from(self.wins) + f64::from(self.draws) / 2.) / f64::from(self.games())
+    }
+
+    /// Variance of the points scored in a single game
+    fn variance(&self) -> f64 {
+        let score = self.score();
+        let games = f64::from(self.games());
+        (f64::from(self.wins) * (1. - score).powi(2)
+            + f64::from(self.draws) * (0.5 - score).powi(2)
+            + f64::from(self.losses) * score.powi(2))
+            / games
+    }
+
+    /// Elo difference along with the margin of its 95% confidence interval. Nothing can be said
+    /// until both engines have scored some points and lost some.
+    pub fn elo(&self) -> Option<(f64, f64)> {
+        let score = self.score();
+        if self.games() == 0 || score <= 0. || score >= 1. {
+            return None;
+        }
+        let margin = 1.959_964 * (self.variance() / f64::from(self.games())).sqrt();
+        let low = score_to_elo((score - margin).max(f64::EPSILON));
+        let high = score_to_elo((score + margin).min(1. - f64::EPSILON));
+        Some((score_to_elo(score), (high - low) / 2.))
+    }
+
+    /// Log-likelihood ratio of `elo1` against `elo0`, using a normal approximation of the score
+    pub fn llr(&self, sprt: &Sprt) -> f64 {
+        let variance = self.variance();
+        if self.games() == 0 || variance == 0. {
+            return 0.;
+        }
+        let (s0, s1) = (elo_to_score(sprt.elo0), elo_to_score(sprt.elo1));
+        f64::from(self.games()) * (s1 - s0) * (2. * self.score() - s0 - s1) / (2. * variance)
+    }
+}
+
+fn elo_to_score(elo: f64) -> f64 {
+    1. / (1. + 10_f64.powf(-elo / 400.))
+}
+
+fn score_to_elo(score: f64) -> f64 {
+    -400. * (1. / score - 1.).log10()
+}
+
+#[cfg(test)]
+mod sprt_tests {
+    use super::{Sprt, Stats};
+
+    #[test]
+    fn elo() {
+        assert_eq!(Stats { wins: 10, losses: 10, draws: 20 }.elo().map(|(elo, _)| elo), Some(0.));
+        assert_eq!(Stats { wins: 10, losses: 0, draws: 0 }.elo(), None);
+        assert_eq!(Stats::default().elo(), None);
+
+        // Scoring 64% is worth almost exactly 100 Elo
+        let (elo, margin) = Stats { wins: 500, losses: 220, draws: 280 }.elo().unwrap();
+        assert!((elo - 100.).abs() < 1., "{elo}");
+        // More games narrow the error bars
+        let (_, wider) = Stats { wins: 50, losses: 22, draws: 28 }.elo().unwrap();
+        assert!(margin > 0. && margin < wider);
+    }
+
+    #[test]
+    fn llr() {
+        let sprt = Sprt::new(0., 5.);
+        assert!((sprt.lower_bound() + 2.944).abs() < 0.001);
+        assert!((sprt.upper_bound() - 2.944).abs() < 0.001);
+
+        // A score right between the hypotheses favours neither
+        let even = Stats { wins: 100, losses: 100, draws: 200 };
+        assert!(even.llr(&Sprt::new(-5., 5.)).abs() < 1e-9);
+        assert!(even.llr(&sprt) < 0.);
+
+        let strong = Stats { wins: 1200, losses: 1000, draws: 2000 };
+        let weak = Stats { wins: 1000, losses: 1200, draws: 2000 };
+        assert!(strong.llr(&sprt) > sprt.upper_bound());
+        assert!(weak.llr(&sprt) < sprt.lower_bound());
+    }
+}
@@ -0,0 +1,404 @@
+use std::{fmt, iter::Peekable, slice::Iter, str::FromStr, time::Duration};
+
+use crate::{
+    bench::BenchConfig,
+    eval::network::NetError,
+    fen::FenError,
+    search::{game_time::Clock, SearchLimits},
+    types::pieces::Color,
+};
+
+/// A command sent by the GUI. Commands are only checked for being well formed here, checks that
+/// depend on the current position happen once they are executed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum UciCommand {
+    Uci,
+    IsReady,
+    UciNewGame,
+    /// Position to set up, where `None` is the starting position, followed by moves to play
+    Position {
+        fen: Option<String>,
+        moves: Vec<String>,
+    },
+    /// Boxed since the limits carry a whole clock along with its time settings
+    Go(Box<GoCommand>),
+    SetOption {
+        name: String,
+        value: Option<String>,
+    },
+    Stop,
+    PonderHit,
+    Quit,
+    Perft(usize),
+    Bench(BenchConfig),
+    Eval,
+    Display,
+    Debug,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct GoCommand {
+    /// Limits as sent by the GUI, before any time has been allocated
+    pub limits: SearchLimits,
+    pub ponder: bool,
+    pub search_moves: Vec<String>,
+    /// Limits left out because their value was missing or malformed, reported once the search
+    /// starts
+    pub ignored: Vec<UciError>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum UciError {
+    UnknownCommand(String),
+    /// A command or option was missing an argument
+    MissingValue(&'static str),
+    InvalidValue {
+        name: String,
+        value: String,
+    },
+    UnknownOption(String),
+    IllegalMove(String),
+    InvalidFen(FenError),
+    /// A file named by a command couldn't be read
+    UnreadableFile(String),
+    InvalidNet(NetError),
+}
+
+impl fmt::Display for UciError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownCommand(cmd) => write!(f, "unknown command '{cmd}'"),
+            Self::MissingValue(name) => write!(f, "missing value for '{name}'"),
+            Self::InvalidValue { name, value } => write!(f, "invalid value '{value}' for '{name}'"),
+            Self::UnknownOption(name) => write!(f, "unknown option '{name}'"),
+            Self::IllegalMove(m) => write!(f, "illegal move '{m}'"),
+            Self::InvalidFen(e) => write!(f, "invalid fen: {e}"),
+            Self::UnreadableFile(e) => write!(f, "couldn't read {e}"),
+            Self::InvalidNet(e) => write!(f, "couldn't load net: {e}"),
+        }
+    }
+}
+
+impl FromStr for UciCommand {
+    type Err = UciError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+        let Some((&cmd, args)) = tokens.split_first() else {
+            return Err(UciError::UnknownCommand(String::new()));
+        };
+        match cmd {
+            "uci" => Ok(Self::Uci),
+            "isready" => Ok(Self::IsReady),
+            "ucinewgame" => Ok(Self::UciNewGame),
+            "position" => parse_position(args),
+            "go" => parse_go(args).map(|go| Self::Go(Box::new(go))),
+            "setoption" => parse_setoption(args),
+            "stop" => Ok(Self::Stop),
+            "ponderhit" => Ok(Self::PonderHit),
+            "quit" => Ok(Self::Quit),
+            "perft" => parse_value("perft", args.first()).map(Self::Perft),
+            "bench" => parse_bench(args).map(Self::Bench),
+            "eval" => Ok(Self::Eval),
+            "d" => Ok(Self::Display),
+            "dbg" => Ok(Self::Debug),
+            _ => Err(UciError::UnknownCommand(cmd.to_string())),
+        }
+    }
+}
+
+/// Parses the value following `name`, failing if it is missing or malformed
+pub fn parse_value<T: FromStr>(name: &'static str, value: Option<&&str>) -> Result<T, UciError> {
+    let value = value.ok_or(UciError::MissingValue(name))?;
+    value.parse().map_err(|_| UciError::InvalidValue { name: name.to_string(), value: value.to_string() })
+}
+
+fn parse_position(args: &[&str]) -> Result<UciCommand, UciError> {
+    let moves_idx = args.iter().position(|&x| x == "moves").unwrap_or(args.len());
+    let moves = args.get(moves_idx + 1..).unwrap_or_default().iter().map(ToString::to_string).collect();
+    let fen = match args.first() {
+        Some(&"startpos") => None,
+        Some(&"fen") if moves_idx > 1 => Some(args[1..moves_idx].join(" ")),
+        Some(&"fen") => return Err(UciError::MissingValue("fen")),
+        Some(other) => return Err(UciError::InvalidValue { name: "position".to_string(), value: other.to_string() }),
+        None => return Err(UciError::MissingValue("position")),
+    };
+    Ok(UciCommand::Position { fen, moves })
+}
+
+/// Arguments of `bench` are positional, so each one can only be given along with those before it
+pub fn parse_bench(args: &[&str]) -> Result<BenchConfig, UciError> {
+    let mut config = BenchConfig::default();
+    let invalid = |name: &str, value: &str| UciError::InvalidValue { name: name.to_string(), value: value.to_string() };
+    if let Some(depth) = args.first() {
+        config.depth = parse_value("depth", Some(depth))?;
+        if config.depth < 1 {
+            return Err(invalid("depth", depth));
+        }
+    }
+    if let Some(threads) = args.get(1) {
+        config.threads = parse_value("threads", Some(threads))?;
+        if !(1..=64).contains(&config.threads) {
+            return Err(invalid("threads", threads));
+        }
+    }
+    if let Some(hash) = args.get(2) {
+        config.hash = parse_value("hash", Some(hash))?;
+        if config.hash == 0 {
+            return Err(invalid("hash", hash));
+        }
+    }
+    match args.get(3..) {
+        Some([]) | None => (),
+        Some(path) => config.fen_file = Some(path.join(" ")),
+    }
+    Ok(config)
+}
+
+/// Tokens that end the list of moves following `searchmoves`, and are never taken as the value of
+/// the limit before them
+const GO_KEYWORDS: [&str; 12] = [
+    "depth",
+    "nodes",
+    "mate",
+    "movetime",
+    "wtime",
+    "btime",
+    "winc",
+    "binc",
+    "movestogo",
+    "infinite",
+    "ponder",
+    "searchmoves",
+];
+
+/// Collects every limit given to `go`. Limits may be combined, and the search stops at whichever is
+/// reached first. A go always has to be answered with a bestmove, so limits without a valid value
+/// are left out rather than failing the command. Only when no limit is left to end the search, and
+/// it wasn't meant to be infinite, is the command rejected.
+fn parse_go(args: &[&str]) -> Result<GoCommand, UciError> {
+    let mut go = GoCommand::default();
+    let mut clock = Clock::default();
+    let mut timed = false;
+    let mut infinite = false;
+    let mut iter = args.iter().peekable();
+    while let Some(&token) = iter.next() {
+        let result = match token {
+            "depth" => parse_value("depth", go_value(&mut iter)).map(|depth| go.limits.depth = Some(depth)),
+            "nodes" => parse_value("nodes", go_value(&mut iter)).map(|nodes| go.limits.nodes = Some(nodes)),
+            "mate" => parse_value("mate", go_value(&mut iter)).map(|mate| go.limits.mate = Some(mate)),
+            "movetime" => parse_millis("movetime", go_value(&mut iter)).map(|time| go.limits.movetime = Some(time)),
+            "wtime" => parse_millis("wtime", go_value(&mut iter)).map(|time| {
+                clock.time_remaining[Color::White] = time;
+                timed = true;
+            }),
+            "btime" => parse_millis("btime", go_value(&mut iter)).map(|time| {
+                clock.time_remaining[Color::Black] = time;
+                timed = true;
+            }),
+            "winc" => parse_millis("winc", go_value(&mut iter)).map(|inc| clock.time_inc[Color::White] = inc),
+            "binc" => parse_millis("binc", go_value(&mut iter)).map(|inc| clock.time_inc[Color::Black] = inc),
+            "movestogo" => parse_value("movestogo", go_value(&mut iter)).map(|moves| clock.movestogo = moves),
+            "ponder" => {
+                go.ponder = true;
+                Ok(())
+            }
+            "infinite" => {
+                infinite = true;
+                Ok(())
+            }
+            "searchmoves" => {
+                while let Some(m) = iter.next_if(|x| !GO_KEYWORDS.contains(x)) {
+                    go.search_moves.push(m.to_string());
+                }
+                Ok(())
+            }
+            // The protocol asks for unknown tokens to be ignored, so extensions sent by some GUIs
+            // don't cost the GUI its bestmove
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            go.ignored.push(e);
+        }
+    }
+    if timed {
+        go.limits.clock = Some(clock);
+    }
+    if go.limits == SearchLimits::default() && !infinite && !go.ponder && !go.ignored.is_empty() {
+        return Err(go.ignored.swap_remove(0));
+    }
+    Ok(go)
+}
+
+/// The value of a `go` limit, unless the limit is directly followed by another one
+fn go_value<'a>(iter: &mut Peekable<Iter<'a, &'a str>>) -> Option<&'a &'a str> {
+    iter.next_if(|x| !GO_KEYWORDS.contains(x))
+}
+
+/// GUIs occasionally send negative times when they are late, which are treated as barely any time
+fn parse_millis(name: &'static str, value: Option<&&str>) -> Result<Duration, UciError> {
+    let millis = parse_value::<i64>(name, value)?.max(1);
+    Ok(Duration::from_millis(millis as u64))
+}
+
+fn parse_setoption(args: &[&str]) -> Result<UciCommand, UciError> {
+    let Some((&"name", args)) = args.split_first() else { return Err(UciError::MissingValue("name")) };
+    let value_idx = args.iter().position(|&x| x == "value");
+    let name = args[..value_idx.unwrap_or(args.len())].join(" ");
+    if name.is_empty() {
+        return Err(UciError::MissingValue("name"));
+    }
+    let value = match value_idx {
+        Some(idx) if idx + 1 < args.len() => Some(args[idx + 1..].join(" ")),
+        Some(_) => return Err(UciError::MissingValue("value")),
+        None => None,
+    };
+    Ok(UciCommand::SetOption { name, value })
+}
+
+#[cfg(test)]
+mod command_tests {
+    use std::time::Duration;
+
+    use super::{UciCommand, UciError};
+    use crate::{bench::BenchConfig, search::SearchLimits, types::pieces::Color};
+
+    fn parse(line: &str) -> Result<UciCommand, UciError> {
+        line.parse()
+    }
+
+    #[test]
+    fn simple_commands() {
+        assert_eq!(parse("uci"), Ok(UciCommand::Uci));
+        assert_eq!(parse("  isready  "), Ok(UciCommand::IsReady));
+        assert_eq!(parse("perft 5"), Ok(UciCommand::Perft(5)));
+        assert_eq!(parse("bench"), Ok(UciCommand::Bench(BenchConfig::default())));
+        assert_eq!(parse("xyzzy 1 2"), Err(UciError::UnknownCommand("xyzzy".to_string())));
+    }
+
+    #[test]
+    fn malformed_perft() {
+        assert_eq!(parse("perft"), Err(UciError::MissingValue("perft")));
+        assert_eq!(
+            parse("perft -1"),
+            Err(UciError::InvalidValue { name: "perft".to_string(), value: "-1".to_string() })
+        );
+    }
+
+    #[test]
+    fn bench() {
+        assert_eq!(
+            parse("bench 10 4"),
+            Ok(UciCommand::Bench(BenchConfig { depth: 10, threads: 4, ..Default::default() }))
+        );
+        assert_eq!(
+            parse("bench 12 2 64 my positions.fen"),
+            Ok(UciCommand::Bench(BenchConfig {
+                depth: 12,
+                threads: 2,
+                hash: 64,
+                fen_file: Some("my positions.fen".to_string())
+            }))
+        );
+        assert!(matches!(parse("bench 0"), Err(UciError::InvalidValue { .. })));
+        assert!(matches!(parse("bench 10 0"), Err(UciError::InvalidValue { .. })));
+        assert!(matches!(parse("bench 10 1 lots"), Err(UciError::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn position() {
+        assert_eq!(
+            parse("position startpos moves e2e4 e7e5"),
+            Ok(UciCommand::Position { fen: None, moves: vec!["e2e4".to_string(), "e7e5".to_string()] })
+        );
+        assert_eq!(
+            parse("position fen 8/8/8/8/8/8/8/K1k5 w - - 0 1"),
+            Ok(UciCommand::Position { fen: Some("8/8/8/8/8/8/8/K1k5 w - - 0 1".to_string()), moves: Vec::new() })
+        );
+        assert_eq!(parse("position"), Err(UciError::MissingValue("position")));
+        assert_eq!(parse("position fen"), Err(UciError::MissingValue("fen")));
+        assert_eq!(parse("position fen moves e2e4"), Err(UciError::MissingValue("fen")));
+        assert!(matches!(parse("position start"), Err(UciError::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn setoption() {
+        assert_eq!(
+            parse("setoption name Move Overhead value 100"),
+            Ok(UciCommand::SetOption { name: "Move Overhead".to_string(), value: Some("100".to_string()) })
+        );
+        assert_eq!(
+            parse("setoption name Clear Hash"),
+            Ok(UciCommand::SetOption { name: "Clear Hash".to_string(), value: None })
+        );
+        assert_eq!(parse("setoption"), Err(UciError::MissingValue("name")));
+        assert_eq!(parse("setoption name"), Err(UciError::MissingValue("name")));
+        assert_eq!(parse("setoption name Hash value"), Err(UciError::MissingValue("value")));
+        assert_eq!(parse("setoption Hash value 16"), Err(UciError::MissingValue("name")));
+    }
+
+    #[test]
+    fn search_moves() {
+        let Ok(UciCommand::Go(go)) = parse("go searchmoves e2e4 g1f3 wtime 1000") else { panic!() };
+        assert_eq!(go.search_moves, ["e2e4", "g1f3"]);
+        assert!(go.limits.clock.is_some());
+
+        let Ok(UciCommand::Go(go)) = parse("go depth 5") else { panic!() };
+        assert!(go.search_moves.is_empty());
+    }
+
+    #[test]
+    fn combined_limits() {
+        let Ok(UciCommand::Go(go)) = parse("go wtime 10000 btime 9000 depth 20 nodes 1000000") else { panic!() };
+        assert_eq!(go.limits.depth, Some(20));
+        assert_eq!(go.limits.nodes, Some(1_000_000));
+        assert_eq!(go.limits.mate, None);
+        assert_eq!(go.limits.movetime, None);
+        let clock = go.limits.clock.unwrap();
+        assert_eq!(clock.time_remaining[Color::White], Duration::from_millis(10000));
+        assert_eq!(clock.time_remaining[Color::Black], Duration::from_millis(9000));
+
+        let Ok(UciCommand::Go(go)) = parse("go ponder movetime 1000 mate 3") else { panic!() };
+        assert_eq!(go.limits.mate, Some(3));
+        assert_eq!(go.limits.movetime, Some(Duration::from_millis(1000)));
+        assert!(go.limits.clock.is_none());
+        assert!(go.ponder);
+
+        assert_eq!(parse("go infinite"), Ok(UciCommand::Go(Box::default())));
+    }
+
+    #[test]
+    fn malformed_go() {
+        // Limits without a valid value are left out, keeping the rest of the command, unless nothing
+        // would be left to end the search
+        assert_eq!(parse("go depth"), Err(UciError::MissingValue("depth")));
+        let Ok(UciCommand::Go(go)) = parse("go depth infinite") else { panic!() };
+        assert_eq!(go.limits, SearchLimits::default());
+        assert_eq!(go.ignored, [UciError::MissingValue("depth")]);
+
+        let Ok(UciCommand::Go(go)) = parse("go nodes lots depth 8") else { panic!() };
+        assert_eq!(go.limits.nodes, None);
+        assert_eq!(go.limits.depth, Some(8));
+        assert_eq!(go.ignored, [UciError::InvalidValue { name: "nodes".to_string(), value: "lots".to_string() }]);
+
+        let Ok(UciCommand::Go(go)) = parse("go wtime x btime 1000 depth movetime 50") else { panic!() };
+        assert_eq!(go.limits.clock.unwrap().time_remaining[Color::Black], Duration::from_millis(1000));
+        assert_eq!(go.limits.depth, None);
+        assert_eq!(go.limits.movetime, Some(Duration::from_millis(50)));
+        assert_eq!(go.ignored.len(), 2);
+
+        // Late GUIs may send negative times
+        let Ok(UciCommand::Go(go)) = parse("go wtime -50 btime 100") else { panic!() };
+        assert_eq!(go.limits.clock.unwrap().time_remaining[Color::White], Duration::from_millis(1));
+    }
+
+    #[test]
+    fn unknown_go_tokens() {
+        let Ok(UciCommand::Go(go)) = parse("go wtime 1000 foo btime 1000") else { panic!() };
+        let clock = go.limits.clock.unwrap();
+        assert_eq!(clock.time_remaining[Color::White], Duration::from_millis(1000));
+        assert_eq!(clock.time_remaining[Color::Black], Duration::from_millis(1000));
+
+        assert_eq!(parse("go sideways"), Ok(UciCommand::Go(Box::default())));
+    }
+}
@@ -0,0 +1,354 @@
+use std::collections::VecDeque;
+use std::process::exit;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::{io, thread, time::Duration};
+
+use std::str::FromStr;
+
+use crate::bench::bench;
+use crate::board::Board;
+use crate::chess_move::{Move, CHESS960};
+use crate::eval::{load_net, trained_net_loaded, EMBEDDED_NET, NO_NET_WARNING};
+use crate::fen::STARTING_FEN;
+use crate::search::game_time::TimeOptions;
+use crate::search::lmr_table::LmrTable;
+use crate::thread::ThreadPool;
+use crate::transposition::{TranspositionTable, TARGET_TABLE_SIZE_MB};
+
+mod command;
+
+pub use command::{parse_bench, GoCommand, UciCommand, UciError};
+
+pub const ENGINE_NAME: &str = "Titan";
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Main loop that handles UCI communication with GUIs
+pub fn main_loop() -> ! {
+    let mut transpos_table = Arc::new(TranspositionTable::new(TARGET_TABLE_SIZE_MB));
+    let mut board = Board::from_fen(STARTING_FEN);
+    let lmr = LmrTable::new();
+    let commands = spawn_stdin_reader();
+    // Commands received during a search that still need to be handled. While searching only
+    // isready, stop, ponderhit and quit are acted on, and everything else, setoption and position
+    // included, waits here until the search has sent its bestmove.
+    let mut pending = VecDeque::new();
+    let mut hash_history = Vec::new();
+    let halt = AtomicBool::new(false);
+    let pondering = AtomicBool::new(false);
+    let global_nodes = AtomicU64::new(0);
+    // Workers are scoped to the UCI loop, so they can borrow everything declared above
+    thread::scope(|s| {
+        let mut thread_pool = ThreadPool::new(s, &halt, &pondering, &lmr, &global_nodes);
+        println!("{ENGINE_NAME} v{VERSION} by {}", env!("CARGO_PKG_AUTHORS"));
+
+        loop {
+            // Stdin closed, exit for openbench
+            let line = pending.pop_front().unwrap_or_else(|| commands.recv().unwrap_or_else(|_| exit(0)));
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let result = line.parse::<UciCommand>().and_then(|command| match command {
+                UciCommand::IsReady => {
+                    println!("readyok");
+                    Ok(())
+                }
+                UciCommand::UciNewGame => {
+                    transpos_table.clear();
+                    halt.store(false, Ordering::Relaxed);
+                    thread_pool.reset();
+                    Ok(())
+                }
+                UciCommand::Eval => {
+                    let acc = board.new_accumulator();
+                    println!("raw: {} cp, adjusted: {} cp", acc.raw_evaluate(&board), acc.scaled_evaluate(&board));
+                    Ok(())
+                }
+                UciCommand::Position { fen, moves } => {
+                    position_command(fen.as_deref(), &moves, &mut board, &mut hash_history)
+                }
+                UciCommand::Display => {
+                    dbg!(&board);
+                    Ok(())
+                }
+                UciCommand::Debug => {
+                    dbg!(&board);
+                    board.debug_bitboards();
+                    Ok(())
+                }
+                UciCommand::Bench(config) => bench(&config, &thread_pool, &commands, &mut pending),
+                UciCommand::Go(go) => {
+                    thread_pool.handle_go(&go, &board, &commands, &mut pending, &hash_history, &transpos_table);
+                    Ok(())
+                }
+                UciCommand::Perft(depth) => {
+                    board.perft(depth);
+                    Ok(())
+                }
+                UciCommand::Quit => exit(0),
+                UciCommand::Uci => {
+                    uci_opts();
+                    Ok(())
+                }
+                UciCommand::SetOption { name, value } => {
+                    set_option(&name, value.as_deref(), &mut transpos_table, &mut thread_pool)
+                }
+                // Only meaningful while a search is running
+                UciCommand::Stop | UciCommand::PonderHit => Ok(()),
+            });
+
+            if let Err(e) = result {
+                println!("info string error: {e}");
+                // The GUI waits for a bestmove after every go, even one that couldn't be parsed
+                if line.split_whitespace().next() == Some("go") {
+                    println!("bestmove 0000");
+                }
+            }
+        }
+    });
+    exit(0)
+}
+
+/// Parses the value of a spin option, checking it against the bounds reported to the GUI
+fn parse_spin<T: FromStr + PartialOrd + Copy>(name: &str, value: Option<&str>, min: T, max: T) -> Result<T, UciError> {
+    let invalid = || UciError::InvalidValue { name: name.to_string(), value: value.unwrap_or_default().to_string() };
+    let x = value.ok_or(UciError::MissingValue("value"))?.parse::<T>().map_err(|_| invalid())?;
+    if x < min || x > max {
+        return Err(invalid());
+    }
+    Ok(x)
+}
+
+/// Parses the value of a check option
+fn parse_check(name: &str, value: Option<&str>) -> Result<bool, UciError> {
+    match value.ok_or(UciError::MissingValue("value"))? {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        value => Err(UciError::InvalidValue { name: name.to_string(), value: value.to_string() }),
+    }
+}
+
+fn set_option(
+    name: &str,
+    value: Option<&str>,
+    transpos_table: &mut Arc<TranspositionTable>,
+    thread_pool: &mut ThreadPool,
+) -> Result<(), UciError> {
+    match name.to_ascii_lowercase().as_str() {
+        "hash" => *transpos_table = Arc::new(TranspositionTable::new(parse_spin(name, value, 1, 8_388_608)?)),
+        "clear hash" => transpos_table.clear(),
+        "threads" => thread_pool.add_workers(parse_spin(name, value, 1, 64)?),
+        "multipv" => thread_pool.multi_pv = parse_spin(name, value, 1, 218)?,
+        "move overhead" => {
+            thread_pool.time_options.move_overhead = Duration::from_millis(parse_spin(name, value, 0, 5000)?);
+        }
+        "safetybuffer" => {
+            thread_pool.time_options.safety_buffer = Duration::from_millis(parse_spin(name, value, 0, 10_000)?);
+        }
+        "timeusage" => thread_pool.time_options.time_usage = parse_spin(name, value, 10, 500)?,
+        #[cfg(feature = "tune")]
+        option if stability_scale(&mut thread_pool.time_options, option).is_some() => {
+            let scale = parse_spin(name, value, 10, 500)?;
+            *stability_scale(&mut thread_pool.time_options, option).unwrap() = scale;
+        }
+        // Pondering is driven entirely by `go ponder`, so the value is only checked
+        "ponder" => {
+            parse_check(name, value)?;
+        }
+        "uci_chess960" => CHESS960.store(parse_check(name, value)?, Ordering::Relaxed),
+        "evalfile" => {
+            let path = value.ok_or(UciError::MissingValue("value"))?;
+            // Options are handled between searches, since `handle_go` queues them until the search ends
+            unsafe { load_net(path) }.map_err(UciError::InvalidNet)?;
+            // Evaluations stored by the previous net no longer mean anything
+            transpos_table.clear();
+            thread_pool.reset();
+        }
+        _ => return Err(UciError::UnknownOption(name.to_string())),
+    }
+    Ok(())
+}
+
+/// Entry of the soft limit stability scales behind options such as `MoveStability0`, which are only
+/// exposed for tuning
+#[cfg(feature = "tune")]
+fn stability_scale<'a>(options: &'a mut TimeOptions, option: &str) -> Option<&'a mut u32> {
+    let (table, idx) = match option.strip_prefix("movestability") {
+        Some(idx) => (&mut options.move_stability_scale, idx),
+        None => (&mut options.score_stability_scale, option.strip_prefix("scorestability")?),
+    };
+    table.get_mut(idx.parse::<usize>().ok()?)
+}
+
+/// Reads stdin on its own thread so commands keep arriving while a search is running. The channel
+/// disconnects once stdin is closed.
+fn spawn_stdin_reader() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lines() {
+            let Ok(line) = line else { break };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn uci_opts() {
+    println!("id name {ENGINE_NAME} {VERSION}");
+    println!("id author {}", env!("CARGO_PKG_AUTHORS"));
+    println!("option name Threads type spin default 1 min 1 max 64");
+    println!("option name Hash type spin default 16 min 1 max 8388608");
+    println!("option name MultiPV type spin default 1 min 1 max 218");
+    println!("option name Ponder type check default false");
+    println!("option name UCI_Chess960 type check default false");
+    println!("option name EvalFile type string default {EMBEDDED_NET}");
+    let time_options = TimeOptions::default();
+    println!("option name Move Overhead type spin default {} min 0 max 5000", time_options.move_overhead.as_millis());
+    println!("option name SafetyBuffer type spin default {} min 0 max 10000", time_options.safety_buffer.as_millis());
+    println!("option name TimeUsage type spin default {} min 10 max 500", time_options.time_usage);
+    #[cfg(feature = "tune")]
+    for (name, table) in
+        [("MoveStability", time_options.move_stability_scale), ("ScoreStability", time_options.score_stability_scale)]
+    {
+        for (idx, scale) in table.iter().enumerate() {
+            println!("option name {name}{idx} type spin default {scale} min 10 max 500");
+        }
+    }
+    if !trained_net_loaded() {
+        println!("info string warning: {NO_NET_WARNING}");
+    }
+    println!("uciok");
+}
+
+/// Sets up the requested position, leaving the current board untouched if any part of the command
+/// turns out to be invalid
+fn position_command(
+    fen: Option<&str>,
+    moves: &[String],
+    board: &mut Board,
+    hash_history: &mut Vec<u64>,
+) -> Result<(), UciError> {
+    let mut new_board = Board::try_from_fen(fen.unwrap_or(STARTING_FEN)).map_err(UciError::InvalidFen)?;
+    let mut new_history = Vec::new();
+    for str in moves {
+        let m = find_move(str, &new_board).ok_or_else(|| UciError::IllegalMove(str.clone()))?;
+        new_board = new_board.make_move(m);
+        new_history.push(new_board.zobrist_hash);
+    }
+    *board = new_board;
+    *hash_history = new_history;
+    Ok(())
+}
+
+/// Finds the legal move matching a move in UCI notation
+pub fn find_move(str: &str, board: &Board) -> Option<Move> {
+    board.legal_moves().iter().find(|m| m.to_uci() == str)
+}
+
+#[cfg(test)]
+mod uci_tests {
+    use std::{
+        collections::VecDeque,
+        sync::{
+            atomic::{AtomicBool, AtomicU64},
+            mpsc, Arc,
+        },
+        thread,
+    };
+
+    use super::{position_command, set_option, UciCommand, UciError};
+    use crate::{
+        board::Board,
+        fen::{FenError, STARTING_FEN},
+        search::lmr_table::LmrTable,
+        thread::ThreadPool,
+        transposition::TranspositionTable,
+    };
+
+    #[test]
+    fn illegal_moves_leave_board_untouched() {
+        let mut board = Board::from_fen(STARTING_FEN);
+        let mut hash_history = Vec::new();
+        let moves = ["e2e4", "e7e5"].map(String::from);
+        assert_eq!(position_command(None, &moves, &mut board, &mut hash_history), Ok(()));
+        assert_eq!(hash_history.len(), 2);
+        let fen = board.to_fen();
+
+        for m in ["e2e4", "e1e3", "zz", "g1f3q", ""] {
+            let moves = ["g1f3".to_string(), m.to_string()];
+            assert_eq!(
+                position_command(None, &moves, &mut board, &mut hash_history),
+                Err(UciError::IllegalMove(m.to_string()))
+            );
+            assert_eq!(board.to_fen(), fen);
+            assert_eq!(hash_history.len(), 2);
+        }
+    }
+
+    #[test]
+    fn invalid_fen_leaves_board_untouched() {
+        let mut board = Board::from_fen(STARTING_FEN);
+        let mut hash_history = vec![board.zobrist_hash];
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNX w KQkq - 0 1";
+        assert_eq!(
+            position_command(Some(fen), &[], &mut board, &mut hash_history),
+            Err(UciError::InvalidFen(FenError::InvalidPiece('X')))
+        );
+        assert_eq!(board.to_fen(), STARTING_FEN);
+        assert_eq!(hash_history.len(), 1);
+    }
+
+    #[test]
+    fn setoption_during_search_waits_for_bestmove() {
+        let (halt, pondering, global_nodes) = (AtomicBool::new(false), AtomicBool::new(false), AtomicU64::new(0));
+        let lmr = LmrTable::new();
+        let mut tt = Arc::new(TranspositionTable::new(1));
+        let board = Board::from_fen(STARTING_FEN);
+        let Ok(UciCommand::Go(go)) = "go depth 6".parse() else { panic!("go should parse") };
+        let (sender, commands) = mpsc::channel();
+        let mut pending = VecDeque::new();
+
+        thread::scope(|s| {
+            let mut thread_pool = ThreadPool::new(s, &halt, &pondering, &lmr, &global_nodes);
+            thread_pool.print_uci = false;
+            sender.send("setoption name MultiPV value 3".to_string()).unwrap();
+            thread_pool.handle_go(&go, &board, &commands, &mut pending, &[], &tt);
+            assert_eq!(thread_pool.multi_pv, 1);
+
+            // The main loop handles the command once handle_go has returned, which is after bestmove
+            pending.extend(commands.try_iter());
+            assert_eq!(pending, ["setoption name MultiPV value 3"]);
+            let Ok(UciCommand::SetOption { name, value }) = pending[0].parse() else {
+                panic!("setoption should parse")
+            };
+            assert_eq!(set_option(&name, value.as_deref(), &mut tt, &mut thread_pool), Ok(()));
+            assert_eq!(thread_pool.multi_pv, 3);
+        });
+    }
+
+    #[test]
+    fn check_options_only_take_true_or_false() {
+        let (halt, pondering, global_nodes) = (AtomicBool::new(false), AtomicBool::new(false), AtomicU64::new(0));
+        let lmr = LmrTable::new();
+        let mut tt = Arc::new(TranspositionTable::new(1));
+        thread::scope(|s| {
+            let mut thread_pool = ThreadPool::new(s, &halt, &pondering, &lmr, &global_nodes);
+            let mut set = |name: &str, value: &str| set_option(name, Some(value), &mut tt, &mut thread_pool);
+            assert_eq!(set("Ponder", "true"), Ok(()));
+            assert_eq!(set("Ponder", "false"), Ok(()));
+            assert_eq!(
+                set("Ponder", "maybe"),
+                Err(UciError::InvalidValue { name: "Ponder".into(), value: "maybe".into() })
+            );
+            assert_eq!(
+                set("UCI_Chess960", "1"),
+                Err(UciError::InvalidValue { name: "UCI_Chess960".into(), value: "1".into() })
+            );
+            assert_eq!(set_option("Ponder", None, &mut tt, &mut thread_pool), Err(UciError::MissingValue("value")));
+        });
+    }
+}
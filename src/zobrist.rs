@@ -90,15 +90,15 @@ mod hashing_test {
     fn incremental_generation() {
         let board = Board::from_fen("k7/3n4/8/2Q5/4pP2/8/8/K7 b - f3 0 1");
         let mut en_p = board;
-        en_p = en_p.make_move(Move::from_san("e4f3", &board));
+        en_p = en_p.make_move(Move::from_uci("e4f3", &board));
         assert_eq!(en_p.zobrist_hash, en_p.generate_hash());
 
         let mut capture = board;
-        capture = capture.make_move(Move::from_san("d7c5", &capture));
+        capture = capture.make_move(Move::from_uci("d7c5", &capture));
         assert_eq!(capture.zobrist_hash, capture.generate_hash());
 
         let mut quiet = board;
-        quiet = quiet.make_move(Move::from_san("a1a2", &quiet));
+        quiet = quiet.make_move(Move::from_uci("a1a2", &quiet));
         assert_eq!(quiet.zobrist_hash, quiet.generate_hash());
     }
 }